@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use crate::hit::Hittable;
 use crate::util::*;
-use crate::vec3::{Ray, Vec3};
+use crate::vec3::{Float, Ray, Vec3};
 
 pub struct AABB {
     _min: Vec3,
@@ -22,36 +22,23 @@ impl AABB {
     pub fn max(&self) -> Vec3 {
         self._max
     }
-    pub fn hit(&self, r: &Ray, mut t_min: f32, mut t_max: f32) -> bool {
-        for a in 0..3 {
-            let inv_d = 1.0 / r.direction()[a];
-            let mut t0 = (self.min()[a] - r.origin()[a]) * inv_d;
-            let mut t1 = (self.max()[a] - r.origin()[a]) * inv_d;
-            if inv_d < 0.0 {
-                std::mem::swap(&mut t0, &mut t1);
-            }
-            t_min = if t0 > t_min { t0 } else { t_min };
-            t_max = if t1 < t_max { t1 } else { t_max };
-            if t_max <= t_min {
-                return false;
-            }
-        }
-        true
+    pub fn hit(&self, r: &Ray, t_min: Float, t_max: Float) -> bool {
+        // All three slabs at once. A ray parallel to a slab and lying in one
+        // of its planes gets 0 * inf = NaN there; as in a scalar slab test,
+        // that plane counts as inside, so the slab places no bound on t.
+        let inv_d = Vec3::splat(1.0) / r.direction();
+        let t0 = ((self.min() - r.origin()) * inv_d).nan_or(-inv_d);
+        let t1 = ((self.max() - r.origin()) * inv_d).nan_or(inv_d);
+        // Widen the far slab by its rounding error so flat boxes, such as
+        // those around rects, are still hit
+        let t_near = t0.min(t1).max_component();
+        let t_far = t0.max(t1).min_component() * (1.0 + 2.0 * gamma(3));
+        t_min.max(t_near) <= t_max.min(t_far)
     }
 }
 
 pub fn surrounding_bbox(bbox0: AABB, bbox1: AABB) -> AABB {
-    let small = Vec3::new(
-        bbox0.min().x().min(bbox1.min().x()),
-        bbox0.min().y().min(bbox1.min().y()),
-        bbox0.min().z().min(bbox1.min().z()),
-    );
-    let big = Vec3::new(
-        bbox0.max().x().max(bbox1.max().x()),
-        bbox0.max().y().max(bbox1.max().y()),
-        bbox0.max().z().max(bbox1.max().z()),
-    );
-    AABB::new(small, big)
+    AABB::new(bbox0.min().min(bbox1.min()), bbox0.max().max(bbox1.max()))
 }
 
 pub struct BvhNode {
@@ -61,7 +48,7 @@ pub struct BvhNode {
 }
 
 impl BvhNode {
-    pub fn new(list: &mut [Arc<dyn Hittable>], time0: f32, time1: f32) -> Self {
+    pub fn new(list: &mut [Arc<dyn Hittable>], time0: Float, time1: Float) -> Self {
         let axis = (3.0 * rand_float()) as u32;
         match axis {
             0 => {
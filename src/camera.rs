@@ -1,5 +1,5 @@
 use crate::util::*;
-use crate::vec3::{cross, Ray, Vec3};
+use crate::vec3::{consts, cross, Float, Ray, RayDifferentials, Vec3};
 
 #[derive(Default)]
 #[allow(dead_code)]
@@ -11,9 +11,9 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
-    lens_radius: f32,
-    time0: f32,
-    time1: f32,
+    lens_radius: Float,
+    time0: Float,
+    time1: Float,
 }
 
 impl Camera {
@@ -21,15 +21,15 @@ impl Camera {
         lookfrom: Vec3,
         lookat: Vec3,
         vup: Vec3,
-        vfov: f32,
-        aspect: f32,
-        aperture: f32,
-        focus_dist: f32,
-        time0: f32,
-        time1: f32,
+        vfov: Float,
+        aspect: Float,
+        aperture: Float,
+        focus_dist: Float,
+        time0: Float,
+        time1: Float,
     ) -> Self {
         let lens_radius = aperture / 2.0;
-        let theta = vfov * std::f32::consts::PI / 180.0;
+        let theta = vfov * consts::PI / 180.0;
         let half_height = (theta / 2.0).tan();
         let half_width = aspect * half_height;
         let origin = lookfrom;
@@ -57,13 +57,35 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
+    // Ray through the center of the lens at the start of the shutter, for
+    // queries that need the same answer every time
+    pub fn get_pinhole_ray(&self, s: Float, t: Float) -> Ray {
+        self.lens_ray(s, t, Vec3::default(), self.time0)
+    }
+
+    // Ray for (s, t) along with rays through (s + ds, t) and (s, t + dt)
+    // sharing its lens sample and time
+    pub fn get_ray_differential(&self, s: Float, t: Float, ds: Float, dt: Float) -> Ray {
         let rd = self.lens_radius * random_in_unit_disk();
-        let offset = self.u * rd.x() + self.v * rd.y();
         let time = self.time0 + rand_float() * (self.time1 - self.time0);
+        let rx = self.lens_ray(s + ds, t, rd, time);
+        let ry = self.lens_ray(s, t + dt, rd, time);
+        self.lens_ray(s, t, rd, time)
+            .with_differentials(Some(RayDifferentials {
+                rx_origin: rx.origin(),
+                rx_direction: rx.direction(),
+                ry_origin: ry.origin(),
+                ry_direction: ry.direction(),
+            }))
+    }
+
+    // Directions are unit length, so that t along camera rays is distance
+    fn lens_ray(&self, s: Float, t: Float, rd: Vec3, time: Float) -> Ray {
+        let offset = self.u * rd.x() + self.v * rd.y();
+        let target = self.ll_corner + s * self.horizontal + t * self.vertical;
         Ray::new(
             self.origin + offset,
-            self.ll_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            (target - self.origin - offset).unit(),
             time,
         )
     }
@@ -0,0 +1,176 @@
+use std::ops::*;
+
+use crate::vec3::{consts, Float, Vec3};
+
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Complex {
+    pub re: Float,
+    pub im: Float,
+}
+
+impl Complex {
+    pub fn new(re: Float, im: Float) -> Self {
+        Self { re, im }
+    }
+    pub fn real(re: Float) -> Self {
+        Self { re, im: 0.0 }
+    }
+    // Squared magnitude
+    pub fn norm(&self) -> Float {
+        self.re * self.re + self.im * self.im
+    }
+    // Principal square root
+    pub fn sqrt(&self) -> Self {
+        let n = self.norm().sqrt();
+        if n == 0.0 {
+            return Complex::real(0.0);
+        }
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0.0 {
+            Complex::new(t1, t2)
+        } else {
+            Complex::new(t2.abs(), t1.copysign(self.im))
+        }
+    }
+    pub fn exp_i(theta: Float) -> Self {
+        Complex::new(theta.cos(), theta.sin())
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, o: Self) -> Self {
+        Complex::new(self.re + o.re, self.im + o.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, o: Self) -> Self {
+        Complex::new(self.re - o.re, self.im - o.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, o: Self) -> Self {
+        Complex::new(
+            self.re * o.re - self.im * o.im,
+            self.re * o.im + self.im * o.re,
+        )
+    }
+}
+
+impl Mul<Float> for Complex {
+    type Output = Self;
+    fn mul(self, o: Float) -> Self {
+        Complex::new(self.re * o, self.im * o)
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+    fn div(self, o: Self) -> Self {
+        let scale = 1.0 / o.norm();
+        Complex::new(
+            scale * (self.re * o.re + self.im * o.im),
+            scale * (self.im * o.re - self.re * o.im),
+        )
+    }
+}
+
+// Unpolarized reflectance of a smooth boundary with relative index of
+// refraction eta, for light arriving at cos_i from either side
+pub fn fr_dielectric(cos_i: Float, eta: Float) -> Float {
+    let mut cos_i = cos_i.clamp(-1.0, 1.0);
+    let mut eta = eta;
+    if cos_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_i = -cos_i;
+    }
+    let sin2_i = 1.0 - cos_i * cos_i;
+    let sin2_t = sin2_i / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+// Reflectance of an absorbing boundary with complex index of refraction eta
+pub fn fr_complex(cos_i: Float, eta: Complex) -> Float {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_i = Complex::real(1.0 - cos_i * cos_i);
+    let sin2_t = sin2_i / (eta * eta);
+    let cos_t = (Complex::real(1.0) - sin2_t).sqrt();
+    let ci = Complex::real(cos_i);
+    let r_parl = (eta * ci - cos_t) / (eta * ci + cos_t);
+    let r_perp = (ci - eta * cos_t) / (ci + eta * cos_t);
+    (r_parl.norm() + r_perp.norm()) / 2.0
+}
+
+// fr_complex for each RGB channel of a conductor with index eta + i k
+pub fn fr_conductor(cos_i: Float, eta: Vec3, k: Vec3) -> Vec3 {
+    Vec3::new(
+        fr_complex(cos_i, Complex::new(eta.x(), k.x())),
+        fr_complex(cos_i, Complex::new(eta.y(), k.y())),
+        fr_complex(cos_i, Complex::new(eta.z(), k.z())),
+    )
+}
+
+// Amplitude reflection coefficients of the s and p polarizations at a
+// boundary from index ni to nj, given the cosines on either side
+fn amplitude_rs(ni: Complex, nj: Complex, cos_i: Complex, cos_j: Complex) -> Complex {
+    (ni * cos_i - nj * cos_j) / (ni * cos_i + nj * cos_j)
+}
+
+fn amplitude_rp(ni: Complex, nj: Complex, cos_i: Complex, cos_j: Complex) -> Complex {
+    (nj * cos_i - ni * cos_j) / (nj * cos_i + ni * cos_j)
+}
+
+// Cosine of the angle light makes in medium n having crossed into it from
+// n1 at cos_1, complex past the critical angle or in an absorbing medium
+fn snell_cos(n1: Float, cos_1: Float, n: Complex) -> Complex {
+    let sin2_1 = Complex::real(n1 * n1 * (1.0 - cos_1 * cos_1));
+    (Complex::real(1.0) - sin2_1 / (n * n)).sqrt()
+}
+
+// Unpolarized reflectance of a film of index film_ior and thickness in nm
+// between a medium of index n1 and a substrate of complex index n3, at
+// wavelength lambda in nm (Airy summation of the interfering reflections)
+pub fn fr_thin_film(
+    cos_i: Float,
+    lambda: Float,
+    thickness: Float,
+    n1: Float,
+    film_ior: Float,
+    n3: Complex,
+) -> Float {
+    let cos_1 = cos_i.clamp(0.0, 1.0);
+    let (c1, n2) = (Complex::real(n1), Complex::real(film_ior));
+    let cos1 = Complex::real(cos_1);
+    let cos2 = snell_cos(n1, cos_1, n2);
+    let cos3 = snell_cos(n1, cos_1, n3);
+
+    // Phase difference between successive reflections inside the film
+    let delta = n2 * cos2 * (4.0 * consts::PI * thickness / lambda);
+    // exp(i delta) for complex delta
+    let phase = Complex::exp_i(delta.re) * (-delta.im).exp();
+
+    let airy = |r12: Complex, r23: Complex| {
+        let r = (r12 + r23 * phase) / (Complex::real(1.0) + r12 * r23 * phase);
+        r.norm().min(1.0)
+    };
+    let rs = airy(
+        amplitude_rs(c1, n2, cos1, cos2),
+        amplitude_rs(n2, n3, cos2, cos3),
+    );
+    let rp = airy(
+        amplitude_rp(c1, n2, cos1, cos2),
+        amplitude_rp(n2, n3, cos2, cos3),
+    );
+    (rs + rp) / 2.0
+}
@@ -4,42 +4,234 @@ use rand::seq::SliceRandom;
 use crate::bvh::*;
 use crate::material::Material;
 use crate::obj::*;
+use crate::texture::Footprint;
 use crate::transf::*;
 use crate::util::*;
 use crate::vec3::*;
 
 pub struct HitRecord {
-    pub t: f32,
+    pub t: Float,
     pub p: Vec3,
+    // Absolute floating point error bound on each component of p
+    pub p_error: Vec3,
     pub normal: Vec3,
-    pub u: f32,
-    pub v: f32,
+    // Perturbed normal materials are shaded with, set by normal and bump
+    // maps; None shades with the geometric normal
+    pub shading_normal: Option<Vec3>,
+    pub u: Float,
+    pub v: Float,
+    // Partial derivatives of p with respect to u and v
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub footprint: Footprint,
     pub material: Arc<dyn Material>,
+    // Set by the outermost Tagged object containing the hit
+    pub object_id: Option<usize>,
+    pub name: Option<Arc<str>>,
+    // Wavelength in nm of the path that made the hit, if it has one
+    pub wavelength: Option<Float>,
 }
 
 impl HitRecord {
-    fn new(t: f32, p: Vec3, normal: Vec3, u: f32, v: f32, material: Arc<dyn Material>) -> HitRecord {
+    pub fn new(
+        t: Float,
+        p: Vec3,
+        p_error: Vec3,
+        normal: Vec3,
+        u: Float,
+        v: Float,
+        material: Arc<dyn Material>,
+    ) -> HitRecord {
         HitRecord {
             t: t,
             p: p,
+            p_error: p_error,
             normal: normal,
+            shading_normal: None,
             u: u,
             v: v,
+            dpdu: Vec3::default(),
+            dpdv: Vec3::default(),
+            footprint: Footprint::default(),
             material: material,
+            object_id: None,
+            name: None,
+            wavelength: None,
         }
     }
+    fn with_tangents(mut self, dpdu: Vec3, dpdv: Vec3) -> HitRecord {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+    // Ray leaving the hit point, with its origin offset to the side of the
+    // surface the direction points to
+    pub fn spawn_ray(&self, direction: Vec3, time: Float) -> Ray {
+        let origin = offset_ray_origin(self.p, self.p_error, self.normal, direction);
+        Ray::new(origin, direction, time)
+    }
+    pub fn shading_normal(&self) -> Vec3 {
+        self.shading_normal.unwrap_or(self.normal)
+    }
+    // Unit tangent along dpdu and bitangent along dpdv, orthogonal to n.
+    // Shapes without tangents get an arbitrary pair.
+    pub fn tangents(&self, n: Vec3) -> (Vec3, Vec3) {
+        let mut frame = Onb::new();
+        frame.build_from_w(&n);
+        let t = self.dpdu - dot(self.dpdu, n) * n;
+        if t.mag_sqrd() == 0.0 {
+            return (frame.u(), frame.v());
+        }
+        let t = t.unit();
+        let b = cross(n, t);
+        let b = if dot(b, self.dpdv) < 0.0 { -b } else { b };
+        (t, b)
+    }
+    // Frame materials are evaluated in, with the shading normal as +z and
+    // x along dpdu, which anisotropic materials orient themselves by
+    pub fn shading_frame(&self) -> Onb {
+        let n = self.shading_normal().unit();
+        let (t, b) = self.tangents(n);
+        Onb {
+            axis: vec![t, b, n],
+        }
+    }
+    // Whether the geometric and shading normals agree on whether wo and wi,
+    // both pointing away from the hit, lie on the same side of the surface.
+    // Where they disagree, following wi would leak light through the
+    // surface, so the path is cut.
+    pub fn sides_agree(&self, wo: &Vec3, wi: &Vec3) -> bool {
+        let ns = self.shading_normal();
+        let geometric = dot(*wo, self.normal) * dot(*wi, self.normal) > 0.0;
+        let shading = dot(*wo, ns) * dot(*wi, ns) > 0.0;
+        geometric == shading
+    }
+    // Replaces the shading normal, turned to the side of the geometric one
+    pub fn set_shading_normal(&mut self, n: Vec3) {
+        let n = n.unit();
+        self.shading_normal = Some(if dot(n, self.normal) < 0.0 { -n } else { n });
+    }
+    // Estimates the footprint of r's pixel around the hit from where its
+    // differential rays meet the tangent plane
+    pub fn compute_differentials(&mut self, r: &Ray) {
+        self.footprint = Footprint::default();
+        let rd = match r.differentials {
+            Some(rd) => rd,
+            None => return,
+        };
+        let n = self.normal;
+        let d = dot(n, self.p);
+        let tx = (d - dot(n, rd.rx_origin)) / dot(n, rd.rx_direction);
+        let ty = (d - dot(n, rd.ry_origin)) / dot(n, rd.ry_direction);
+        if !tx.is_finite() || !ty.is_finite() {
+            return;
+        }
+        let dpdx = rd.rx_origin + tx * rd.rx_direction - self.p;
+        let dpdy = rd.ry_origin + ty * rd.ry_direction - self.p;
+        self.footprint.dpdx = dpdx;
+        self.footprint.dpdy = dpdy;
+
+        // Least squares solve for (du, dv) in the two axes the tangent plane
+        // projects onto best
+        let (d0, d1) = if n.x().abs() > n.y().abs() && n.x().abs() > n.z().abs() {
+            (1, 2)
+        } else if n.y().abs() > n.z().abs() {
+            (0, 2)
+        } else {
+            (0, 1)
+        };
+        let (a00, a01) = (self.dpdu[d0], self.dpdv[d0]);
+        let (a10, a11) = (self.dpdu[d1], self.dpdv[d1]);
+        let det = a00 * a11 - a01 * a10;
+        if det.abs() < 1e-12 {
+            return;
+        }
+        let solve =
+            |b0: Float, b1: Float| ((a11 * b0 - a01 * b1) / det, (a00 * b1 - a10 * b0) / det);
+        let (dudx, dvdx) = solve(dpdx[d0], dpdx[d1]);
+        let (dudy, dvdy) = solve(dpdy[d0], dpdy[d1]);
+        self.footprint.dudx = dudx;
+        self.footprint.dvdx = dvdx;
+        self.footprint.dudy = dudy;
+        self.footprint.dvdy = dvdy;
+    }
+    // Differentials for r after a specular bounce into direction wi,
+    // treating the surface as locally flat
+    pub fn specular_differentials(&self, r: &Ray, wi: Vec3) -> Option<RayDifferentials> {
+        let rd = r.differentials?;
+        let d = r.direction().unit();
+        let wi = wi.unit();
+        let n = self.normal;
+        let reflected = dot(d, n) * dot(wi, n) < 0.0;
+        // Recover the relative index of refraction from the tangential
+        // components, which it scales
+        let tangent_in = (d - dot(d, n) * n).mag();
+        let tangent_out = (wi - dot(wi, n) * n).mag();
+        let eta = if tangent_in > 1e-6 {
+            tangent_out / tangent_in
+        } else {
+            1.0
+        };
+        let facing = if dot(d, n) > 0.0 { -n } else { n };
+        let bend = |v: Vec3| {
+            if reflected {
+                reflect(v, n)
+            } else {
+                refract(v, facing, eta).unwrap_or_else(|| reflect(v, n))
+            }
+        };
+        Some(RayDifferentials {
+            rx_origin: self.p + self.footprint.dpdx,
+            rx_direction: bend(rd.rx_direction),
+            ry_origin: self.p + self.footprint.dpdy,
+            ry_direction: bend(rd.ry_direction),
+        })
+    }
+}
+
+pub trait Hittable: Sync + Send {
+    fn hit(&self, r: Ray, t_min: Float, t_max: Float) -> Option<HitRecord>;
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB>;
+    fn pdf_value(&self, _o: &Vec3, _v: &Vec3) -> Float {
+        0.0
+    }
+    fn random(&self, _o: &Vec3) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+}
+
+// Reprojects a point found by solving the quadratic back onto the sphere,
+// returning it with a bound on its remaining error
+fn sphere_point(p: Vec3, center: Vec3, radius: Float) -> (Vec3, Vec3) {
+    let offset = p - center;
+    let offset = offset * (radius.abs() / offset.mag());
+    let point = center + offset;
+    let p_error = gamma(5) * offset.abs() + gamma(1) * point.abs();
+    (point, p_error)
+}
+
+// dp/du and dp/dv for the parametrization in Sphere::get_sphere_uv, with
+// offset the hit point relative to the center
+fn sphere_tangents(offset: Vec3) -> (Vec3, Vec3) {
+    let (x, y, z) = (offset.x(), offset.y(), offset.z());
+    let rho = (x * x + z * z).sqrt().max(1e-8 * offset.mag());
+    let dpdu = 2.0 * consts::PI * Vec3::new(z, 0.0, -x);
+    let dpdv = consts::PI * Vec3::new(-y * x / rho, rho, -y * z / rho);
+    (dpdu, dpdv)
 }
 
-pub trait Hittable : Sync + Send {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
-    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB>;
-    fn pdf_value(&self, _o: &Vec3, _v: &Vec3) -> f32 { 0.0 }
-    fn random(&self, _o: &Vec3) -> Vec3 { Vec3::new(1.0, 0.0, 0.0) }
+// The hit if its material's alpha test lets r stop there
+fn alpha_tested(hit: HitRecord, r: &Ray) -> Option<HitRecord> {
+    if hit.material.alpha_test(&hit, r) {
+        Some(hit)
+    } else {
+        None
+    }
 }
 
 impl Hittable for Sphere {
     // Solves a quadratic equation
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
         let oc = r.origin() - self.center;
         let a = dot(r.b, r.b);
         let b = dot(oc, r.b);
@@ -50,48 +242,48 @@ impl Hittable for Sphere {
             // Check smaller parameter
             let t = (-b - discriminant.sqrt()) / a;
             if t_min < t && t < t_max {
-                let point = r.point_at_parameter(t);
+                let (point, p_error) =
+                    sphere_point(r.point_at_parameter(t), self.center, self.radius);
                 let normal = (point - self.center) / self.radius;
                 let (u, v) = Sphere::get_sphere_uv(&normal);
-                return Some(HitRecord::new(
-                    t,
-                    point,
-                    normal,
-                    u,
-                    v,
-                    Arc::clone(&self.material),
-                ));
+                let (dpdu, dpdv) = sphere_tangents(normal * self.radius);
+                let hit =
+                    HitRecord::new(t, point, p_error, normal, u, v, Arc::clone(&self.material))
+                        .with_tangents(dpdu, dpdv);
+                // Where the near side is cut away, the far side may show
+                if let Some(hit) = alpha_tested(hit, &r) {
+                    return Some(hit);
+                }
             }
 
             // Check larger parameter
             let t = (-b + discriminant.sqrt()) / a;
             if t_min < t && t < t_max {
-                let point = r.point_at_parameter(t);
+                let (point, p_error) =
+                    sphere_point(r.point_at_parameter(t), self.center, self.radius);
                 let normal = (point - self.center) / self.radius;
                 let (u, v) = Sphere::get_sphere_uv(&normal);
-                return Some(HitRecord::new(
-                    t,
-                    point,
-                    normal,
-                    u,
-                    v,
-                    Arc::clone(&self.material),
-                ));
+                let (dpdu, dpdv) = sphere_tangents(normal * self.radius);
+                let hit =
+                    HitRecord::new(t, point, p_error, normal, u, v, Arc::clone(&self.material))
+                        .with_tangents(dpdu, dpdv);
+                return alpha_tested(hit, &r);
             }
         }
         None
     }
-    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
         let bbox = AABB::new(
             self.center - Vec3::new(self.radius, self.radius, self.radius),
             self.center + Vec3::new(self.radius, self.radius, self.radius),
         );
         Some(bbox)
     }
-    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f32 {
-        if let Some(_) = self.hit(Ray::new(*o, *v, 0.0), 0.001, std::f32::MAX) {
-            let cos_theta_max = (1.0 - self.radius*self.radius/(self.center-*o).mag_sqrd()).sqrt();
-            let solid_angle = 2.0 * std::f32::consts::PI * (1.0 - cos_theta_max);
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> Float {
+        if let Some(_) = self.hit(Ray::new(*o, *v, 0.0), 0.0, Float::MAX) {
+            let cos_theta_max =
+                (1.0 - self.radius * self.radius / (self.center - *o).mag_sqrd()).sqrt();
+            let solid_angle = 2.0 * consts::PI * (1.0 - cos_theta_max);
             return 1.0 / solid_angle;
         }
         0.0
@@ -106,8 +298,8 @@ impl Hittable for Sphere {
 }
 
 impl Hittable for Vec<Arc<dyn Hittable>> {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let mut closest_t: f32 = t_max;
+    fn hit(&self, r: Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let mut closest_t: Float = t_max;
         let mut closest_hit: Option<HitRecord> = None;
         for obj in self {
             if let Some(hit) = obj.hit(r, t_min, closest_t) {
@@ -117,7 +309,7 @@ impl Hittable for Vec<Arc<dyn Hittable>> {
         }
         closest_hit
     }
-    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
         if self.is_empty() {
             return None;
         }
@@ -139,8 +331,8 @@ impl Hittable for Vec<Arc<dyn Hittable>> {
         }
         Some(bbox)
     }
-    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f32 {
-        let weight = 1.0 / self.len() as f32;
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> Float {
+        let weight = 1.0 / self.len() as Float;
         let mut sum = 0.0;
 
         for obj in self.iter() {
@@ -155,7 +347,7 @@ impl Hittable for Vec<Arc<dyn Hittable>> {
 
 impl Hittable for MovingSphere {
     // Solves a quadratic equation
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
         let oc = r.origin() - self.center(r.time());
         let a = dot(r.b, r.b);
         let b = dot(oc, r.b);
@@ -166,38 +358,37 @@ impl Hittable for MovingSphere {
             // Check smaller parameter
             let t = (-b - discriminant.sqrt()) / a;
             if t_min < t && t < t_max {
-                let point = r.point_at_parameter(t);
-                let normal = (point - self.center(r.time())) / self.radius;
+                let center = self.center(r.time());
+                let (point, p_error) = sphere_point(r.point_at_parameter(t), center, self.radius);
+                let normal = (point - center) / self.radius;
                 let (u, v) = Sphere::get_sphere_uv(&normal);
-                return Some(HitRecord::new(
-                    t,
-                    point,
-                    normal,
-                    u,
-                    v,
-                    Arc::clone(&self.material),
-                ));
+                let (dpdu, dpdv) = sphere_tangents(normal * self.radius);
+                let hit =
+                    HitRecord::new(t, point, p_error, normal, u, v, Arc::clone(&self.material))
+                        .with_tangents(dpdu, dpdv);
+                // Where the near side is cut away, the far side may show
+                if let Some(hit) = alpha_tested(hit, &r) {
+                    return Some(hit);
+                }
             }
 
             // Check larger parameter
             let t = (-b + discriminant.sqrt()) / a;
             if t_min < t && t < t_max {
-                let point = r.point_at_parameter(t);
-                let normal = (point - self.center(r.time())) / self.radius;
+                let center = self.center(r.time());
+                let (point, p_error) = sphere_point(r.point_at_parameter(t), center, self.radius);
+                let normal = (point - center) / self.radius;
                 let (u, v) = Sphere::get_sphere_uv(&normal);
-                return Some(HitRecord::new(
-                    t,
-                    point,
-                    normal,
-                    u,
-                    v,
-                    Arc::clone(&self.material),
-                ));
+                let (dpdu, dpdv) = sphere_tangents(normal * self.radius);
+                let hit =
+                    HitRecord::new(t, point, p_error, normal, u, v, Arc::clone(&self.material))
+                        .with_tangents(dpdu, dpdv);
+                return alpha_tested(hit, &r);
             }
         }
         None
     }
-    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
         let bbox0 = AABB::new(
             self.center(t0) - Vec3::new(self.radius, self.radius, self.radius),
             self.center(t0) + Vec3::new(self.radius, self.radius, self.radius),
@@ -211,7 +402,7 @@ impl Hittable for MovingSphere {
 }
 
 impl Hittable for BvhNode {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
         match (&self.left, &self.right) {
             (Some(left), Some(right)) => {
                 if self.bbox.hit(&r, t_min, t_max) {
@@ -233,15 +424,15 @@ impl Hittable for BvhNode {
         }
         None
     }
-    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
         Some(AABB::new(self.bbox.min(), self.bbox.max()))
     }
 }
 
 impl Hittable for XYRect {
-    fn hit(&self, r: Ray, t0: f32, t1: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t0: Float, t1: Float) -> Option<HitRecord> {
         let t = (self.k - r.origin().z()) / r.direction().z();
-        if t < t0 || t > t1 {
+        if t <= t0 || t >= t1 {
             return None;
         }
         let x = r.origin().x() + t * r.direction().x();
@@ -251,22 +442,33 @@ impl Hittable for XYRect {
         }
         let u = (x - self.x0) / (self.x1 - self.x0);
         let v = (y - self.y0) / (self.y1 - self.y0);
-        let p = r.point_at_parameter(t);
+        // The point lies exactly on the plane, so only the in-plane
+        // coordinates carry error
+        let mut p = r.point_at_parameter(t);
+        p[2] = self.k;
+        let mut p_error = gamma(3) * p.abs();
+        p_error[2] = 0.0;
         let normal = Vec3::new(0.0, 0.0, 1.0);
-        Some(HitRecord::new(t, p, normal, u, v, self.material.clone()))
+        let (dpdu, dpdv) = (
+            Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+            Vec3::new(0.0, self.y1 - self.y0, 0.0),
+        );
+        let hit = HitRecord::new(t, p, p_error, normal, u, v, self.material.clone())
+            .with_tangents(dpdu, dpdv);
+        alpha_tested(hit, &r)
     }
-    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
         Some(AABB::new(
-            Vec3::new(self.x0, self.y0, self.k - 0.0001),
-            Vec3::new(self.x1, self.y1, self.k + 0.0001),
+            Vec3::new(self.x0, self.y0, self.k),
+            Vec3::new(self.x1, self.y1, self.k),
         ))
     }
 }
 
 impl Hittable for XZRect {
-    fn hit(&self, r: Ray, t0: f32, t1: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t0: Float, t1: Float) -> Option<HitRecord> {
         let t = (self.k - r.origin().y()) / r.direction().y();
-        if t < t0 || t > t1 {
+        if t <= t0 || t >= t1 {
             return None;
         }
         let x = r.origin().x() + t * r.direction().x();
@@ -276,18 +478,29 @@ impl Hittable for XZRect {
         }
         let u = (x - self.x0) / (self.x1 - self.x0);
         let v = (z - self.z0) / (self.z1 - self.z0);
-        let p = r.point_at_parameter(t);
+        // The point lies exactly on the plane, so only the in-plane
+        // coordinates carry error
+        let mut p = r.point_at_parameter(t);
+        p[1] = self.k;
+        let mut p_error = gamma(3) * p.abs();
+        p_error[1] = 0.0;
         let normal = Vec3::new(0.0, 1.0, 0.0);
-        Some(HitRecord::new(t, p, normal, u, v, self.material.clone()))
+        let (dpdu, dpdv) = (
+            Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, self.z1 - self.z0),
+        );
+        let hit = HitRecord::new(t, p, p_error, normal, u, v, self.material.clone())
+            .with_tangents(dpdu, dpdv);
+        alpha_tested(hit, &r)
     }
-    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
         Some(AABB::new(
-            Vec3::new(self.x0, self.k - 0.0001, self.z0),
-            Vec3::new(self.x1, self.k + 0.0001, self.z1),
+            Vec3::new(self.x0, self.k, self.z0),
+            Vec3::new(self.x1, self.k, self.z1),
         ))
     }
-    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f32 {
-        if let Some(hit) = self.hit(Ray::new(*o, *v, 0.0), 0.001, std::f32::MAX) {
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> Float {
+        if let Some(hit) = self.hit(Ray::new(*o, *v, 0.0), 0.0, Float::MAX) {
             let area = (self.x1 - self.x0) * (self.z1 - self.z0);
             let dist_sqrd = hit.t * hit.t * v.mag().powi(2);
             let cosine = (dot(*v, hit.normal) / v.mag()).abs();
@@ -304,9 +517,9 @@ impl Hittable for XZRect {
 }
 
 impl Hittable for YZRect {
-    fn hit(&self, r: Ray, t0: f32, t1: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t0: Float, t1: Float) -> Option<HitRecord> {
         let t = (self.k - r.origin().x()) / r.direction().x();
-        if t < t0 || t > t1 {
+        if t <= t0 || t >= t1 {
             return None;
         }
         let y = r.origin().y() + t * r.direction().y();
@@ -316,51 +529,83 @@ impl Hittable for YZRect {
         }
         let u = (y - self.y0) / (self.y1 - self.y0);
         let v = (z - self.z0) / (self.z1 - self.z0);
-        let p = r.point_at_parameter(t);
+        // The point lies exactly on the plane, so only the in-plane
+        // coordinates carry error
+        let mut p = r.point_at_parameter(t);
+        p[0] = self.k;
+        let mut p_error = gamma(3) * p.abs();
+        p_error[0] = 0.0;
         let normal = Vec3::new(1.0, 0.0, 0.0);
-        Some(HitRecord::new(t, p, normal, u, v, self.material.clone()))
+        let (dpdu, dpdv) = (
+            Vec3::new(0.0, self.y1 - self.y0, 0.0),
+            Vec3::new(0.0, 0.0, self.z1 - self.z0),
+        );
+        let hit = HitRecord::new(t, p, p_error, normal, u, v, self.material.clone())
+            .with_tangents(dpdu, dpdv);
+        alpha_tested(hit, &r)
     }
-    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
         Some(AABB::new(
-            Vec3::new(self.k - 0.0001, self.y0, self.z0),
-            Vec3::new(self.k + 0.0001, self.y1, self.z1),
+            Vec3::new(self.k, self.y0, self.z0),
+            Vec3::new(self.k, self.y1, self.z1),
         ))
     }
 }
 
 impl Hittable for FlipNormals {
-    fn hit(&self, r: Ray, t0: f32, t1: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t0: Float, t1: Float) -> Option<HitRecord> {
         if let Some(mut hit) = self.obj_ref.hit(r, t0, t1) {
             hit.normal *= -1.0;
             return Some(hit);
         }
         None
     }
-    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
         self.obj_ref.bounding_box(t0, t1)
     }
 }
 
+impl Hittable for Tagged {
+    fn hit(&self, r: Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        if let Some(mut hit) = self.obj_ref.hit(r, t_min, t_max) {
+            hit.object_id = Some(self.id);
+            hit.name = self.name.clone();
+            return Some(hit);
+        }
+        None
+    }
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        self.obj_ref.bounding_box(t0, t1)
+    }
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> Float {
+        self.obj_ref.pdf_value(o, v)
+    }
+    fn random(&self, o: &Vec3) -> Vec3 {
+        self.obj_ref.random(o)
+    }
+}
+
 impl Hittable for BoxShape {
-    fn hit(&self, r: Ray, t0: f32, t1: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t0: Float, t1: Float) -> Option<HitRecord> {
         self.faces.hit(r, t0, t1)
     }
-    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, _t0: Float, _t1: Float) -> Option<AABB> {
         Some(AABB::new(self.pmin, self.pmax))
     }
 }
 
 impl Hittable for Translate {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
         let moved_ray = Ray::new(r.origin() - self.offset, r.direction(), r.time());
 
         if let Some(mut hit) = self.obj_ref.hit(moved_ray, t_min, t_max) {
             hit.p += self.offset;
+            hit.p_error += gamma(1) * hit.p.abs();
             return Some(hit);
         }
         None
     }
-    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
         if let Some(bbox) = self.obj_ref.bounding_box(t0, t1) {
             return Some(AABB::new(
                 bbox.min() + self.offset,
@@ -372,7 +617,7 @@ impl Hittable for Translate {
 }
 
 impl Hittable for RotateY {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
         let mut origin = r.origin();
         let mut direction = r.direction();
 
@@ -383,32 +628,40 @@ impl Hittable for RotateY {
         direction[2] = self.sin_theta * r.direction()[0] + self.cos_theta * r.direction()[2];
         let rotate_r = Ray::new(origin, direction, r.time());
         if let Some(mut hit) = self.obj_ref.hit(rotate_r, t_min, t_max) {
-            let mut p = hit.p;
-            let mut normal = hit.normal;
-            p[0] = self.cos_theta * hit.p[0] + self.sin_theta * hit.p[2];
-            p[2] = -self.sin_theta * hit.p[0] + self.cos_theta * hit.p[2];
-            normal[0] = self.cos_theta * hit.normal[0] + self.sin_theta * hit.normal[2];
-            normal[2] = -self.sin_theta * hit.normal[0] + self.cos_theta * hit.normal[2];
+            let p = self.to_world(hit.p);
+            let normal = self.to_world(hit.normal);
+            let (c, s) = (self.cos_theta.abs(), self.sin_theta.abs());
+            let err = hit.p_error;
+            let abs_p = hit.p.abs();
+            let rotated_err = Vec3::new(c * err[0] + s * err[2], err[1], s * err[0] + c * err[2]);
+            let rotated_abs_p = Vec3::new(
+                c * abs_p[0] + s * abs_p[2],
+                abs_p[1],
+                s * abs_p[0] + c * abs_p[2],
+            );
+            hit.p_error = (gamma(3) + 1.0) * rotated_err + gamma(3) * rotated_abs_p;
             hit.p = p;
             hit.normal = normal;
+            hit.dpdu = self.to_world(hit.dpdu);
+            hit.dpdv = self.to_world(hit.dpdv);
             return Some(hit);
         }
         None
     }
-    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
         if let Some(bbox) = self.obj_ref.bounding_box(t0, t1) {
             let mut max = Vec3::new(
-                std::f32::NEG_INFINITY,
-                std::f32::NEG_INFINITY,
-                std::f32::NEG_INFINITY,
+                Float::NEG_INFINITY,
+                Float::NEG_INFINITY,
+                Float::NEG_INFINITY,
             );
-            let mut min = Vec3::new(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY);
+            let mut min = Vec3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY);
             for i in 0..2 {
                 for j in 0..2 {
                     for k in 0..2 {
-                        let x = i as f32 * bbox.max().x() + (1.0 - i as f32) * bbox.min().x();
-                        let y = j as f32 * bbox.max().y() + (1.0 - j as f32) * bbox.min().y();
-                        let z = k as f32 * bbox.max().z() + (1.0 - k as f32) * bbox.min().z();
+                        let x = i as Float * bbox.max().x() + (1.0 - i as Float) * bbox.min().x();
+                        let y = j as Float * bbox.max().y() + (1.0 - j as Float) * bbox.min().y();
+                        let z = k as Float * bbox.max().z() + (1.0 - k as Float) * bbox.min().z();
 
                         let new_x = self.cos_theta * x + self.sin_theta * z;
                         let new_z = -self.sin_theta * x + self.cos_theta * z;
@@ -431,12 +684,9 @@ impl Hittable for RotateY {
 }
 
 impl Hittable for ConstantMedium {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        if let Some(mut hit1) = self
-            .boundary
-            .hit(r, std::f32::NEG_INFINITY, std::f32::INFINITY)
-        {
-            if let Some(mut hit2) = self.boundary.hit(r, hit1.t + 0.0001, std::f32::INFINITY) {
+    fn hit(&self, r: Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        if let Some(mut hit1) = self.boundary.hit(r, Float::NEG_INFINITY, Float::INFINITY) {
+            if let Some(mut hit2) = self.boundary.hit(r, hit1.t, Float::INFINITY) {
                 if hit1.t < t_min {
                     hit1.t = t_min;
                 }
@@ -457,6 +707,7 @@ impl Hittable for ConstantMedium {
                     return Some(HitRecord::new(
                         t,
                         p,
+                        Vec3::new(0.0, 0.0, 0.0),
                         Vec3::new(1.0, 0.0, 0.0),
                         0.0,
                         0.0,
@@ -467,7 +718,216 @@ impl Hittable for ConstantMedium {
         }
         None
     }
-    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
         self.boundary.bounding_box(t0, t1)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Cutout, Lambertian};
+    use crate::texture::{ConstantTexture, Texture};
+
+    const SCALES: [Float; 5] = [0.001, 1.0, 555.0, 10000.0, 100000.0];
+    const TRIALS: usize = 2000;
+
+    fn white() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Arc::new(ConstantTexture::new(Vec3::new(
+            0.73, 0.73, 0.73,
+        )))))
+    }
+
+    // Random direction on the side of the surface given by sign
+    fn random_in_hemisphere(normal: Vec3, sign: Float) -> Vec3 {
+        let d = random_unit_vector();
+        if dot(d, normal) * sign > 0.0 {
+            d
+        } else {
+            -1.0 * d
+        }
+    }
+
+    #[test]
+    fn sphere_reflected_rays_escape() {
+        for &scale in SCALES.iter() {
+            let center = Vec3::new(0.3, 1.7, -2.1) * scale;
+            let sphere = Sphere::new(center, scale, white());
+            for _ in 0..TRIALS {
+                let origin = center + 3.0 * scale * random_unit_vector();
+                let target = center + 0.9 * scale * random_in_unit_sphere();
+                let r = Ray::new(origin, target - origin, 0.0);
+                let hit = sphere.hit(r, 0.0, Float::MAX).expect("camera ray missed");
+
+                let d = random_in_hemisphere(hit.normal, 1.0);
+                let spawned = hit.spawn_ray(d, 0.0);
+                assert!(
+                    sphere.hit(spawned, 0.0, Float::MAX).is_none(),
+                    "self-intersection at scale {}",
+                    scale
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_transmitted_rays_reach_far_side() {
+        for &scale in SCALES.iter() {
+            let center = Vec3::new(-4.0, 0.5, 9.0) * scale;
+            let sphere = Sphere::new(center, scale, white());
+            for _ in 0..TRIALS {
+                let origin = center + 3.0 * scale * random_unit_vector();
+                let target = center + 0.5 * scale * random_in_unit_sphere();
+                let r = Ray::new(origin, target - origin, 0.0);
+                let hit = sphere.hit(r, 0.0, Float::MAX).expect("camera ray missed");
+
+                // Close to the inward normal, so the chord is long
+                let d = -1.0 * hit.normal + 0.3 * random_in_unit_sphere();
+                let spawned = hit.spawn_ray(d, 0.0);
+                let exit = sphere
+                    .hit(spawned, 0.0, Float::MAX)
+                    .expect("transmitted ray escaped through the surface");
+                assert!((exit.p - hit.p).mag() > scale, "re-hit the entry point");
+            }
+        }
+    }
+
+    #[test]
+    fn rotated_box_reflected_rays_escape() {
+        for &scale in SCALES.iter() {
+            let shape = Arc::new(BoxShape::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(165.0, 330.0, 165.0) * (scale / 555.0),
+                white(),
+            ));
+            let offset = Vec3::new(265.0, 0.0, 295.0) * (scale / 555.0);
+            let tall_box = Translate::new(Arc::new(RotateY::new(shape, 15.0)), offset);
+            let center = offset + Vec3::new(82.5, 165.0, 82.5) * (scale / 555.0);
+            for _ in 0..TRIALS {
+                let origin = center + 2.0 * scale * random_unit_vector();
+                let r = Ray::new(origin, center - origin, 0.0);
+                let hit = tall_box.hit(r, 0.0, Float::MAX).expect("camera ray missed");
+
+                let d = random_in_hemisphere(hit.normal, -dot(r.direction(), hit.normal));
+                let spawned = hit.spawn_ray(d, 0.0);
+                assert!(
+                    tall_box.hit(spawned, 0.0, Float::MAX).is_none(),
+                    "self-intersection at scale {}",
+                    scale
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn flat_rect_bbox_is_hit() {
+        for &scale in SCALES.iter() {
+            let rect = XZRect::new(0.0, scale, 0.0, scale, scale, white());
+            let bbox = rect.bounding_box(0.0, 0.0).unwrap();
+            let r = Ray::new(
+                Vec3::new(0.5, 0.0, 0.5) * scale,
+                Vec3::new(0.1, 1.0, -0.2),
+                0.0,
+            );
+            assert!(bbox.hit(&r, 0.0, Float::MAX));
+            assert!(rect.hit(r, 0.0, Float::MAX).is_some());
+        }
+    }
+
+    #[test]
+    fn rays_in_slab_planes_hit_bbox() {
+        // Axis-parallel rays lying exactly in each face plane, travelling
+        // both ways, touch the box; those just outside it miss
+        let bbox = AABB::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 2.0, 3.0));
+        for &dir in [1.0, -1.0].iter() {
+            for &(x, expected) in [(0.0, true), (1.0, true), (-0.5, false), (1.5, false)].iter() {
+                let r = Ray::new(Vec3::new(x, 1.0, -5.0 * dir), Vec3::new(0.0, 0.0, dir), 0.0);
+                assert_eq!(
+                    bbox.hit(&r, 0.0, Float::MAX),
+                    expected,
+                    "x {} dir {}",
+                    x,
+                    dir
+                );
+                let r = Ray::new(Vec3::new(0.5, 2.0 * x, 1.5), Vec3::new(dir, 0.0, 0.0), 0.0);
+                assert_eq!(
+                    bbox.hit(&r, 0.0, Float::MAX),
+                    expected,
+                    "y {} dir {}",
+                    x,
+                    dir
+                );
+            }
+        }
+    }
+
+    // Opaque only where z is positive
+    struct FrontHalf;
+
+    impl Texture for FrontHalf {
+        fn value(&self, _u: Float, _v: Float, p: &Vec3) -> Vec3 {
+            let a = if p.z() > 0.0 { 1.0 } else { 0.0 };
+            Vec3::new(a, a, a)
+        }
+    }
+
+    #[test]
+    fn cutouts_pass_rays_through() {
+        let clear: Arc<dyn Material> = Arc::new(Cutout::new(
+            white(),
+            Arc::new(ConstantTexture::new(Vec3::default())),
+        ));
+        let rect = XZRect::new(-1.0, 1.0, -1.0, 1.0, 0.0, clear);
+        let down = Ray::new(Vec3::new(0.2, 1.0, 0.3), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        assert!(rect.hit(down, 0.0, Float::MAX).is_none());
+
+        // The far side of a sphere shows where the near side is cut away
+        let half: Arc<dyn Material> = Arc::new(Cutout::new(white(), Arc::new(FrontHalf)));
+        let sphere = Sphere::new(Vec3::default(), 1.0, half);
+        let from_back = Ray::new(Vec3::new(0.0, 0.0, -3.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let hit = sphere.hit(from_back, 0.0, Float::MAX).unwrap();
+        assert!((hit.p.z() - 1.0).abs() < 1e-4);
+        let from_front = Ray::new(Vec3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(sphere.hit(from_front, 0.0, Float::MAX).unwrap().p.z() > 0.0);
+
+        // Half opacity stops about half the rays, and the same ray always
+        let half: Arc<dyn Material> = Arc::new(Cutout::new(
+            white(),
+            Arc::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5))),
+        ));
+        let rect = XZRect::new(-1.0, 1.0, -1.0, 1.0, 0.0, half);
+        let mut stopped = 0;
+        for _ in 0..TRIALS {
+            let origin = Vec3::new(rand_float() - 0.5, 1.0, rand_float() - 0.5);
+            let r = Ray::new(origin, Vec3::new(0.0, -1.0, 0.0), 0.0);
+            let hit = rect.hit(r, 0.0, Float::MAX).is_some();
+            assert_eq!(hit, rect.hit(r, 0.0, Float::MAX).is_some());
+            stopped += hit as usize;
+        }
+        let fraction = stopped as Float / TRIALS as Float;
+        assert!((fraction - 0.5).abs() < 0.05, "{}", fraction);
+    }
+
+    #[test]
+    fn subsurface_steps_are_fixed_per_ray() {
+        let ball = |mean_free_path: Float| {
+            let sphere = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, white()));
+            let mfp = Vec3::new(mean_free_path, mean_free_path, mean_free_path);
+            SubsurfaceMedium::new(sphere, mfp, Vec3::new(0.9, 0.9, 0.9), 1.4)
+        };
+
+        // Each ray from inside takes one step, however often it is tested
+        let wax = ball(0.5);
+        for _ in 0..100 {
+            let r = Ray::new(0.5 * random_in_unit_sphere(), random_unit_vector(), 0.0);
+            let t = wax.hit(r, 0.0, Float::MAX).unwrap().t;
+            assert_eq!(wax.hit(r, 0.0, Float::MAX).unwrap().t, t);
+        }
+
+        // Steps shorter than t_min still scatter, at t_min
+        let dense = ball(1e-6);
+        let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let hit = dense.hit(r, 1e-3, Float::MAX).unwrap();
+        assert_eq!(hit.t, 1e-3);
+    }
+}
@@ -0,0 +1,700 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use image::{ColorType, GenericImageView};
+
+use crate::material::*;
+use crate::merl::Merl;
+use crate::perlin::Perlin;
+use crate::texture::*;
+use crate::vec3::*;
+
+// Materials and textures shared between scenes under names, so that scenes
+// ask for "white" instead of each building their own. An override replaces
+// every material handed out, e.g. with gray clay to judge lighting and
+// geometry on their own.
+#[derive(Default)]
+pub struct MaterialLibrary {
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    // Materials the override leaves alone, so that clay renders are still lit
+    lights: HashSet<String>,
+    override_material: Option<Arc<dyn Material>>,
+}
+
+fn solid(r: Float, g: Float, b: Float) -> Arc<dyn Texture> {
+    Arc::new(ConstantTexture::new(Vec3::new(r, g, b)))
+}
+
+impl MaterialLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The materials the built-in scenes are made of
+    pub fn standard() -> Self {
+        let mut lib = Self::new();
+        lib.add_material("white", Arc::new(Lambertian::new(solid(0.73, 0.73, 0.73))));
+        lib.add_material("red", Arc::new(Lambertian::new(solid(0.65, 0.05, 0.05))));
+        lib.add_material("green", Arc::new(Lambertian::new(solid(0.12, 0.45, 0.15))));
+        lib.add_material("glass", Arc::new(Dielectric::new(1.5)));
+        lib.add_light(
+            "light",
+            Arc::new(DiffuseLight::new(solid(15.0, 15.0, 15.0))),
+        );
+        lib.add_light(
+            "dim light",
+            Arc::new(DiffuseLight::new(solid(7.0, 7.0, 7.0))),
+        );
+        lib
+    }
+
+    pub fn add_texture(&mut self, name: &str, texture: Arc<dyn Texture>) {
+        self.textures.insert(name.to_string(), texture);
+    }
+
+    pub fn add_material(&mut self, name: &str, material: Arc<dyn Material>) {
+        self.lights.remove(name);
+        self.materials.insert(name.to_string(), material);
+    }
+
+    // Adds an emitter, which keeps its material under an override
+    pub fn add_light(&mut self, name: &str, material: Arc<dyn Material>) {
+        self.materials.insert(name.to_string(), material);
+        self.lights.insert(name.to_string());
+    }
+
+    // Hands out material for every name but the lights from now on
+    pub fn with_override(mut self, material: Arc<dyn Material>) -> Self {
+        self.override_material = Some(material);
+        self
+    }
+
+    // Overrides with a single mid-gray Lambertian
+    pub fn with_clay(self) -> Self {
+        self.with_override(Arc::new(Lambertian::new(solid(0.5, 0.5, 0.5))))
+    }
+
+    // Adds everything in other, replacing entries with the same names
+    pub fn extend(&mut self, other: MaterialLibrary) {
+        self.textures.extend(other.textures);
+        for (name, material) in other.materials {
+            if other.lights.contains(&name) {
+                self.add_light(&name, material);
+            } else {
+                self.add_material(&name, material);
+            }
+        }
+        if other.override_material.is_some() {
+            self.override_material = other.override_material;
+        }
+    }
+
+    pub fn get_texture(&self, name: &str) -> Option<Arc<dyn Texture>> {
+        self.textures.get(name).cloned()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Material>> {
+        let material = self.materials.get(name)?;
+        match &self.override_material {
+            Some(o) if !self.lights.contains(name) => Some(o.clone()),
+            _ => Some(material.clone()),
+        }
+    }
+
+    // As get, for scene code where a missing name is a bug
+    pub fn material(&self, name: &str) -> Arc<dyn Material> {
+        match self.get(name) {
+            Some(m) => m,
+            None => panic!("no material named {:?} in the library", name),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    // One entry per line, with # starting a comment:
+    //
+    //   texture <name> constant <r> <g> <b>
+    //   texture <name> noise <scale>
+    //   texture <name> checker <r> <g> <b> <r> <g> <b>
+    //   texture <name> image <path>
+    //   material <name> lambertian <color>
+    //   material <name> oren_nayar <color> <sigma>
+    //   material <name> metal <r> <g> <b> <fuzz>
+    //   material <name> conductor <metal> <roughness> [film <thickness> <ior>]
+    //       [roughness_v <roughness>] [rotation <degrees>] [tangents <color>]
+    //   material <name> principled <color> [<parameter> <value>]...
+    //   material <name> coated <material> <ior> [roughness <value>]
+    //       [absorption <r> <g> <b> <thickness>]
+    //   material <name> mix <material> <material> <weight>
+    //   material <name> cutout <material> <opacity> [threshold <t>]
+    //   material <name> cutout <material> alpha <texture> [threshold <t>]
+    //   material <name> normal_map <material> <color>
+    //   material <name> bump_map <material> <height> <scale>
+    //   material <name> sheen <color> <roughness> [base <material>]
+    //   material <name> dielectric <ior> [absorption <r> <g> <b>]
+    //       [film <thickness> <ior>] [dispersion <glass>]
+    //   material <name> rough_dielectric <ior> <roughness> [absorption <r> <g> <b>]
+    //   material <name> thin_dielectric <ior>
+    //   material <name> merl <path>
+    //   light <name> <color> [<scale>] [two_sided] [power <watts> <area>]
+    //       [lumens <lumens> <area>]
+    //   light <name> blackbody <kelvin> ...
+    //
+    // where a color is either three numbers or the name of a texture defined
+    // above it, and a value (roughness, weight, opacity, height, film thickness
+    // in nm and the principled parameters) either a number or such a texture's
+    // first channel. Metals are gold, silver, copper or aluminium, and
+    // roughness_v makes them brushed, rough by roughness along a tangent and by
+    // roughness_v across it. The tangent is dpdu turned by rotation, or from a
+    // flow map. Principled parameters are metallic, roughness, specular,
+    // specular_tint, sheen, sheen_tint, clearcoat, clearcoat_gloss, transmission
+    // and ior, which takes a number. Coats, mixes, cutouts, normal and bump maps
+    // and sheen bases are made of materials defined above them; a mix weights
+    // the second by its weight, and a cutout is opaque where its opacity, or the
+    // alpha of an image texture with one, is one. Dispersive glass is bk7,
+    // fused_silica, diamond, cauchy <a> <b> or sellmeier <b1> <b2> <b3> <c1>
+    // <c2> <c3>, and its index replaces the one given. A blackbody light takes
+    // the same options after its temperature, and a light's power in watts or
+    // lumens is spread over its area and the sides it emits from. Subsurface
+    // scattering fills a shape rather than covering it, so scenes build it with
+    // SubsurfaceMedium and it has no entry here. Names may not contain spaces.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut lib = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            lib.parse_entry(&words).map_err(|msg| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", i + 1, msg),
+                )
+            })?;
+        }
+        Ok(lib)
+    }
+
+    fn parse_entry(&mut self, words: &[&str]) -> Result<(), String> {
+        let (kind, name) = match words {
+            [kind, name, ..] => (*kind, *name),
+            _ => return Err("expected an entry kind and a name".to_string()),
+        };
+        let mut args = Args { words: &words[2..] };
+        match kind {
+            "texture" => {
+                let texture: Arc<dyn Texture> = match args.word()? {
+                    "constant" => Arc::new(ConstantTexture::new(args.vec3()?)),
+                    "noise" => Arc::new(NoiseTexture::new(args.float()?, Perlin::new())),
+                    "checker" => {
+                        let odd = Box::new(ConstantTexture::new(args.vec3()?));
+                        let even = Box::new(ConstantTexture::new(args.vec3()?));
+                        Arc::new(CheckerTexture::new(odd, even))
+                    }
+                    "image" => {
+                        let path = args.word()?;
+                        let img = image::open(path).map_err(|e| format!("{}: {}", path, e))?;
+                        let (nx, ny) = (img.dimensions().0 as i32, img.dimensions().1 as i32);
+                        // Images with an alpha channel keep it, for cutouts
+                        match img.color() {
+                            ColorType::RGBA(_) | ColorType::BGRA(_) | ColorType::GrayA(_) => {
+                                Arc::new(ImageTexture::new_rgba(img.to_rgba().into_raw(), nx, ny))
+                            }
+                            _ => Arc::new(ImageTexture::new(img.raw_pixels(), nx, ny)),
+                        }
+                    }
+                    other => return Err(format!("unknown texture type {:?}", other)),
+                };
+                args.finish()?;
+                self.add_texture(name, texture);
+            }
+            "material" => {
+                let material: Arc<dyn Material> = match args.word()? {
+                    "lambertian" => Arc::new(Lambertian::new(self.color(&mut args)?)),
+                    "oren_nayar" => {
+                        let albedo = self.color(&mut args)?;
+                        Arc::new(OrenNayar::new(albedo, args.float()?))
+                    }
+                    "metal" => {
+                        let albedo = args.vec3()?;
+                        Arc::new(Metal::new(albedo, args.float()?))
+                    }
+                    "conductor" => {
+                        let metal = args.word()?;
+                        let roughness = self.scalar(&mut args)?;
+                        let mut conductor = match metal {
+                            "gold" => Conductor::gold(roughness),
+                            "silver" => Conductor::silver(roughness),
+                            "copper" => Conductor::copper(roughness),
+                            "aluminium" => Conductor::aluminium(roughness),
+                            other => return Err(format!("unknown metal {:?}", other)),
+                        };
+                        while !args.words.is_empty() {
+                            conductor = match args.word()? {
+                                "film" => conductor.with_thin_film(self.film(&mut args)?),
+                                "roughness_v" => {
+                                    conductor.with_roughness_v(self.scalar(&mut args)?)
+                                }
+                                "rotation" => conductor.with_tangent_rotation(args.float()?),
+                                "tangents" => conductor.with_tangent_map(self.color(&mut args)?),
+                                other => return Err(format!("unknown parameter {:?}", other)),
+                            };
+                        }
+                        Arc::new(conductor)
+                    }
+                    "principled" => {
+                        let mut p = Principled::new(self.color(&mut args)?);
+                        while !args.words.is_empty() {
+                            p = match args.word()? {
+                                "metallic" => p.with_metallic(self.scalar(&mut args)?),
+                                "roughness" => p.with_roughness(self.scalar(&mut args)?),
+                                "specular" => p.with_specular(self.scalar(&mut args)?),
+                                "specular_tint" => p.with_specular_tint(self.scalar(&mut args)?),
+                                "sheen" => p.with_sheen(self.scalar(&mut args)?),
+                                "sheen_tint" => p.with_sheen_tint(self.scalar(&mut args)?),
+                                "clearcoat" => p.with_clearcoat(self.scalar(&mut args)?),
+                                "clearcoat_gloss" => {
+                                    p.with_clearcoat_gloss(self.scalar(&mut args)?)
+                                }
+                                "transmission" => p.with_transmission(self.scalar(&mut args)?),
+                                "ior" => p.with_ior(args.float()?),
+                                other => return Err(format!("unknown parameter {:?}", other)),
+                            };
+                        }
+                        Arc::new(p)
+                    }
+                    "coated" => {
+                        let base = self.layer(&mut args)?;
+                        let mut coat = Coated::new(base, args.float()?);
+                        while !args.words.is_empty() {
+                            coat = match args.word()? {
+                                "roughness" => coat.with_roughness(self.scalar(&mut args)?),
+                                "absorption" => {
+                                    let sigma_a = args.vec3()?;
+                                    coat.with_absorption(sigma_a, args.float()?)
+                                }
+                                other => return Err(format!("unknown parameter {:?}", other)),
+                            };
+                        }
+                        Arc::new(coat)
+                    }
+                    "mix" => {
+                        let (a, b) = (self.layer(&mut args)?, self.layer(&mut args)?);
+                        Arc::new(Mix::new(a, b, self.scalar(&mut args)?))
+                    }
+                    "cutout" => {
+                        let material = self.layer(&mut args)?;
+                        let mut cutout = if args.words.first() == Some(&"alpha") {
+                            args.word()?;
+                            let name = args.word()?;
+                            let texture = self
+                                .get_texture(name)
+                                .ok_or_else(|| format!("no texture named {:?}", name))?;
+                            Cutout::from_alpha(material, texture)
+                        } else {
+                            Cutout::new(material, self.scalar(&mut args)?)
+                        };
+                        while !args.words.is_empty() {
+                            cutout = match args.word()? {
+                                "threshold" => cutout.with_threshold(args.float()?),
+                                other => return Err(format!("unknown parameter {:?}", other)),
+                            };
+                        }
+                        Arc::new(cutout)
+                    }
+                    "normal_map" => {
+                        let material = self.layer(&mut args)?;
+                        Arc::new(NormalMap::new(material, self.color(&mut args)?))
+                    }
+                    "bump_map" => {
+                        let material = self.layer(&mut args)?;
+                        let height = self.scalar(&mut args)?;
+                        Arc::new(BumpMap::new(material, height, args.float()?))
+                    }
+                    "sheen" => {
+                        let color = self.color(&mut args)?;
+                        let mut sheen = Sheen::new(color, self.scalar(&mut args)?);
+                        while !args.words.is_empty() {
+                            sheen = match args.word()? {
+                                "base" => sheen.with_base(self.layer(&mut args)?),
+                                other => return Err(format!("unknown parameter {:?}", other)),
+                            };
+                        }
+                        Arc::new(sheen)
+                    }
+                    "dielectric" => {
+                        let mut glass = Dielectric::new(args.float()?);
+                        while !args.words.is_empty() {
+                            glass = match args.word()? {
+                                "absorption" => glass.with_absorption(args.vec3()?),
+                                "film" => glass.with_thin_film(self.film(&mut args)?),
+                                "dispersion" => glass.with_dispersion(dispersion(&mut args)?),
+                                other => return Err(format!("unknown parameter {:?}", other)),
+                            };
+                        }
+                        Arc::new(glass)
+                    }
+                    "rough_dielectric" => {
+                        let ior = args.float()?;
+                        let mut glass = RoughDielectric::new(ior, self.scalar(&mut args)?);
+                        while !args.words.is_empty() {
+                            glass = match args.word()? {
+                                "absorption" => glass.with_absorption(args.vec3()?),
+                                other => return Err(format!("unknown parameter {:?}", other)),
+                            };
+                        }
+                        Arc::new(glass)
+                    }
+                    "thin_dielectric" => Arc::new(ThinDielectric::new(args.float()?)),
+                    "merl" => {
+                        let path = args.word()?;
+                        Arc::new(Merl::load(path).map_err(|e| format!("{}: {}", path, e))?)
+                    }
+                    other => return Err(format!("unknown material type {:?}", other)),
+                };
+                args.finish()?;
+                self.add_material(name, material);
+            }
+            "light" => {
+                let mut light = match args.words.first() {
+                    Some(&"blackbody") => {
+                        args.word()?;
+                        DiffuseLight::blackbody(args.float()?)
+                    }
+                    _ => DiffuseLight::new(self.color(&mut args)?),
+                };
+                if args
+                    .words
+                    .first()
+                    .is_some_and(|w| w.parse::<Float>().is_ok())
+                {
+                    light = light.with_scale(args.float()?);
+                }
+                while !args.words.is_empty() {
+                    light = match args.word()? {
+                        "two_sided" => light.with_two_sided(true),
+                        "power" => light.with_power(args.float()?, args.float()?),
+                        "lumens" => light.with_lumens(args.float()?, args.float()?),
+                        other => return Err(format!("unknown parameter {:?}", other)),
+                    };
+                }
+                self.add_light(name, Arc::new(light));
+            }
+            other => return Err(format!("unknown entry kind {:?}", other)),
+        }
+        Ok(())
+    }
+
+    // Three numbers for a constant color, or the name of a known texture
+    fn color(&self, args: &mut Args) -> Result<Arc<dyn Texture>, String> {
+        match args.words.first() {
+            Some(w) if w.parse::<Float>().is_err() => {
+                args.words = &args.words[1..];
+                self.get_texture(w)
+                    .ok_or_else(|| format!("no texture named {:?}", w))
+            }
+            _ => Ok(Arc::new(ConstantTexture::new(args.vec3()?))),
+        }
+    }
+
+    // The name of a known material, which other materials are built on.
+    // Overrides do not apply to those.
+    fn layer(&self, args: &mut Args) -> Result<Arc<dyn Material>, String> {
+        let name = args.word()?;
+        self.materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("no material named {:?}", name))
+    }
+
+    // A film's thickness in nm, as a value, then its index of refraction
+    fn film(&self, args: &mut Args) -> Result<ThinFilm, String> {
+        let thickness = self.scalar(args)?;
+        Ok(ThinFilm::new(thickness, args.float()?))
+    }
+
+    // A number, or the name of a known texture
+    fn scalar(&self, args: &mut Args) -> Result<Arc<dyn Texture>, String> {
+        match args.words.first() {
+            Some(w) if w.parse::<Float>().is_err() => self.color(args),
+            _ => {
+                let v = args.float()?;
+                Ok(Arc::new(ConstantTexture::new(Vec3::new(v, v, v))))
+            }
+        }
+    }
+}
+
+// Glass by name or by the coefficients of a model, as in the format above
+fn dispersion(args: &mut Args) -> Result<Dispersion, String> {
+    Ok(match args.word()? {
+        "bk7" => Dispersion::bk7(),
+        "fused_silica" => Dispersion::fused_silica(),
+        "diamond" => Dispersion::diamond(),
+        "cauchy" => Dispersion::Cauchy {
+            a: args.float()?,
+            b: args.float()?,
+        },
+        "sellmeier" => {
+            let b = [args.float()?, args.float()?, args.float()?];
+            let c = [args.float()?, args.float()?, args.float()?];
+            Dispersion::Sellmeier { b, c }
+        }
+        other => return Err(format!("unknown dispersion {:?}", other)),
+    })
+}
+
+// The rest of an entry's words, consumed front to back
+struct Args<'a> {
+    words: &'a [&'a str],
+}
+
+impl<'a> Args<'a> {
+    fn word(&mut self) -> Result<&'a str, String> {
+        let (first, rest) = self
+            .words
+            .split_first()
+            .ok_or_else(|| "missing argument".to_string())?;
+        self.words = rest;
+        Ok(first)
+    }
+
+    fn float(&mut self) -> Result<Float, String> {
+        let w = self.word()?;
+        w.parse()
+            .map_err(|_| format!("expected a number, found {:?}", w))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.float()?, self.float()?, self.float()?))
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        match self.words.first() {
+            Some(w) => Err(format!("unexpected {:?}", w)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::testing::hit_with;
+
+    // Radiance of a material seen head on
+    fn emitted(m: &Arc<dyn Material>) -> Vec3 {
+        let hit = hit_with(m.clone());
+        let r = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        m.emitted(&r, &hit, 0.0, 0.0, &hit.p)
+    }
+    fn albedo(m: &Arc<dyn Material>) -> Vec3 {
+        let hit = hit_with(m.clone());
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        m.eval(&hit, &up, &up) * consts::PI
+    }
+
+    #[test]
+    fn parse_and_override() {
+        let lib = MaterialLibrary::parse(
+            "# a comment\n\
+             texture tan constant 0.8 0.6 0.4\n\
+             \n\
+             material wall lambertian tan   # by texture\n\
+             material paint lambertian 0.1 0.2 0.3\n\
+             material gold metal 0.8 0.6 0.2 0.1\n\
+             material glass dielectric 1.5\n\
+             material bottle dielectric 1.5 absorption 0.5 0.1 0.5\n\
+             material bubble dielectric 1 film 300 1.33\n\
+             material prism dielectric 1.5 dispersion cauchy 1.5 0.01\n\
+             material tempered conductor silver 0.2 film 250 2.4\n\
+             material brushed conductor aluminium 0.15 roughness_v 0.6 rotation 90\n\
+             material brass conductor copper tan\n\
+             material frosted rough_dielectric 1.5 0.3 absorption 1 1 0\n\
+             material lacquer coated paint 1.5 roughness 0.2 absorption 0 0.5 1 0.1\n\
+             material rusty mix gold paint tan\n\
+             material lace cutout paint 0.25 threshold 0.5\n\
+             texture ripples noise 4\n\
+             texture board checker 0 0 0 1 1 1\n\
+             material tilted normal_map paint 0.6 0.5 0.9\n\
+             material bumpy bump_map paint ripples 0.1\n\
+             material velvet sheen 0.9 0.2 0.3 0.4 base paint\n\
+             material car principled 0.8 0.1 0.1 metallic 0.2 clearcoat 1 ior 1.45\n\
+             light lamp 1 1 1 4\n\
+             light bulb blackbody 2700 power 60 0.5 two_sided\n",
+        )
+        .unwrap();
+        assert!((albedo(&lib.material("wall")).x() - 0.8).abs() < 1e-4);
+        assert!((albedo(&lib.material("paint")).z() - 0.3).abs() < 1e-4);
+        assert!(lib.get("gold").is_some() && lib.get("glass").is_some());
+        let brass = lib.material("brass");
+        assert!(brass.flags(&hit_with(brass.clone())).has_non_specular());
+        let frosted = lib.material("frosted");
+        let flags = frosted.flags(&hit_with(frosted.clone()));
+        assert!(flags.has_non_specular() && flags.contains(BsdfFlags::TRANSMISSION));
+        let car: Arc<dyn Material> = Arc::new(
+            Principled::new(solid(0.8, 0.1, 0.1))
+                .with_metallic(solid(0.2, 0.2, 0.2))
+                .with_clearcoat(solid(1.0, 1.0, 1.0))
+                .with_ior(1.45),
+        );
+        assert!((albedo(&lib.material("car")) - albedo(&car)).mag() < 1e-4);
+        // Light leaving the bottle from inside has crossed it, here 2 units
+        let bottle = lib.material("bottle");
+        let mut hit = hit_with(bottle.clone());
+        hit.t = 2.0;
+        let wo = Vec3::new(0.0, 0.0, -1.0);
+        let bs = bottle.sample(&hit, &wo, (0.9, 0.5)).unwrap();
+        let green = bs.f.y() * bs.wi.z().abs() / bs.pdf;
+        assert!((green - (-0.2 as Float).exp()).abs() < 1e-4, "{}", green);
+        assert!(lib.material("prism").is_dispersive() && !lib.material("glass").is_dispersive());
+
+        // Brushed across the tangent, turned a quarter from dpdu
+        let brushed = lib.material("brushed");
+        let hit = hit_with(brushed.clone());
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        let along_u = brushed.eval(&hit, &wo, &Vec3::new(0.3, 0.0, 1.0).unit());
+        let along_v = brushed.eval(&hit, &wo, &Vec3::new(0.0, 0.3, 1.0).unit());
+        assert!(along_u.x() > 2.0 * along_v.x());
+
+        // Films color what is reflected
+        let bare: [Arc<dyn Material>; 2] = [
+            Arc::new(Dielectric::new(1.0)),
+            Arc::new(Conductor::silver(solid(0.2, 0.2, 0.2))),
+        ];
+        for (name, bare) in ["bubble", "tempered"].iter().zip(bare.iter()) {
+            let reflected = |m: &Arc<dyn Material>| {
+                let bs = m.sample(&hit_with(m.clone()), &Vec3::new(0.0, 0.0, 1.0), (0.0, 0.5));
+                bs.unwrap().f
+            };
+            let (f, f_bare) = (reflected(&lib.material(name)), reflected(bare));
+            let hue = |c: Vec3| c / c.max_component();
+            assert!((hue(f) - hue(f_bare)).mag() > 0.05, "{} {:?}", name, f);
+        }
+        let lacquer = lib.material("lacquer");
+        assert!(lacquer
+            .flags(&hit_with(lacquer.clone()))
+            .contains(BsdfFlags::DIFFUSE));
+        let lace = lib.material("lace");
+        let r = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(!lace.alpha_test(&hit_with(lace.clone()), &r));
+        // Noise is seeded afresh each run, so look for a slope along a line
+        // longer than the ripples' period
+        for name in ["tilted", "bumpy"].iter() {
+            let m = lib.material(name);
+            let tilt = (0..16).map(|i| {
+                let mut hit = hit_with(m.clone());
+                hit.p = Vec3::new(0.3 + 0.1 * i as Float, 0.7, 0.0);
+                hit.dpdu = Vec3::new(1.0, 0.0, 0.0);
+                hit.dpdv = Vec3::new(0.0, 1.0, 0.0);
+                m.apply_shading(&mut hit);
+                hit.shading_normal().z()
+            });
+            let z = tilt.fold(1.0, Float::min);
+            assert!(z < 0.999, "{} {}", name, z);
+        }
+        let velvet = lib.material("velvet");
+        let hit = hit_with(velvet.clone());
+        let (up, grazing) = (Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.95, 0.0, 0.312).unit());
+        assert!(velvet.eval(&hit, &grazing, &grazing).x() > velvet.eval(&hit, &up, &up).x());
+        // Weighted by tan's mean of 0.6
+        let rusty = lib.material("rusty");
+        let (wo, wi) = (Vec3::new(0.6, 0.0, 0.8), Vec3::new(0.0, 0.6, 0.8));
+        let hit = hit_with(rusty.clone());
+        let mixed = 0.4 * lib.material("gold").eval(&hit, &wo, &wi)
+            + 0.6 * lib.material("paint").eval(&hit, &wo, &wi);
+        assert!((rusty.eval(&hit, &wo, &wi) - mixed).mag() < 1e-4);
+        // Checks blur to gray once a footprint spans a cell
+        let board = lib.get_texture("board").unwrap();
+        let p = Vec3::new(0.1, 0.1, 0.1);
+        let wide = Footprint {
+            dpdx: Vec3::new(1.0, 0.0, 0.0),
+            ..Footprint::default()
+        };
+        assert_eq!(board.filtered(0.0, 0.0, &p, &Footprint::default()).x(), 1.0);
+        assert_eq!(board.filtered(0.0, 0.0, &p, &wide).x(), 0.5);
+        assert!(lib.get("missing").is_none());
+        assert!((emitted(&lib.material("lamp")).y() - 4.0).abs() < 1e-4);
+        let bulb: Arc<dyn Material> = Arc::new(
+            DiffuseLight::blackbody(2700.0)
+                .with_two_sided(true)
+                .with_power(60.0, 0.5),
+        );
+        assert!((emitted(&lib.material("bulb")) - emitted(&bulb)).mag() < 1e-4);
+
+        // Clay replaces everything but the lights
+        let clay = lib.with_clay();
+        assert!((albedo(&clay.material("paint")).z() - 0.5).abs() < 1e-4);
+        assert!((albedo(&clay.material("glass")).x() - 0.5).abs() < 1e-4);
+        assert!((emitted(&clay.material("lamp")).y() - 4.0).abs() < 1e-4);
+        assert!(clay.get("missing").is_none());
+
+        let standard = MaterialLibrary::standard();
+        assert!((albedo(&standard.material("red")).x() - 0.65).abs() < 1e-4);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = |text: &str| MaterialLibrary::parse(text).err().expect(text);
+        let e = err("material a lambertian 1 1 1\nmaterial b lambertian nothing\n");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().starts_with("line 2:"), "{}", e);
+        err("material a lambertian 1 1");
+        err("material a dielectric 1.5 2");
+        err("material a dielectric 1.5 absorption 1 1");
+        err("material a conductor gold 0.1 film 300");
+        err("material a conductor gold 0.1 rotation");
+        err("material a dielectric 1.5 dispersion flint");
+        err("material a plastic 1");
+        err("material a conductor tin 0.1");
+        err("material a principled 1 1 1 gloss 1");
+        err("material a principled 1 1 1 metallic");
+        err("material a coated nothing 1.5");
+        err("material a mix glass 0.5");
+        err("material a cutout glass alpha nothing");
+        err("material a bump_map glass 1");
+        err("material a sheen 1 1 1 0.5 base");
+        err("texture t constant x 1 1");
+        err("shape s sphere");
+        err("light");
+        err("light a blackbody");
+        err("light a 1 1 1 power 60");
+        err("light a 1 1 1 4 sideways");
+    }
+
+    #[test]
+    fn image_alpha_cutouts() {
+        // Opaque on the left half, clear on the right
+        let path = std::env::temp_dir().join("shrimpray_library_leaf.png");
+        let alpha = [255, 255, 0, 0];
+        let pixels = alpha.iter().flat_map(|&a| vec![40, 160, 40, a]).collect();
+        image::RgbaImage::from_raw(4, 1, pixels)
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        let lib = MaterialLibrary::parse(&format!(
+            "texture leaf image {}\n\
+             material green lambertian leaf\n\
+             material leaf cutout green alpha leaf threshold 0.5\n",
+            path.display()
+        ))
+        .unwrap();
+        let _ = fs::remove_file(&path);
+
+        let leaf = lib.material("leaf");
+        let r = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let mut hit = hit_with(leaf.clone());
+        hit.u = 0.1;
+        assert!(leaf.alpha_test(&hit, &r));
+        assert!((albedo(&leaf).y() - 160.0 / 255.0).abs() < 1e-2);
+        hit.u = 0.9;
+        assert!(!leaf.alpha_test(&hit, &r));
+    }
+}
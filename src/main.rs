@@ -15,9 +15,21 @@ use hit::*;
 
 mod bvh;
 
+mod fresnel;
+
+mod microfacet;
+
+mod spectrum;
+use spectrum::{sample_wavelength, wavelength_to_rgb};
+
 mod material;
 use material::*;
 
+mod merl;
+
+mod library;
+use library::MaterialLibrary;
+
 mod texture;
 use texture::*;
 
@@ -31,6 +43,12 @@ use pdf::*;
 
 mod transf;
 
+mod query;
+use query::SceneQuery;
+
+mod toon;
+use toon::ToonShader;
+
 mod scene;
 use scene::*;
 
@@ -43,39 +61,172 @@ fn color(
     if depth <= 0 {
         return Vec3::new(0.0, 0.0, 0.0);
     }
-    if let Some(hit) = world.hit(r, 0.001, std::f32::MAX) {
+    if let Some(mut hit) = world.hit(r, 0.0, Float::MAX) {
+        // Paths through dispersive materials carry a single wavelength,
+        // drawn uniformly and weighted by its color
+        if hit.material.is_dispersive() && r.wavelength().is_none() {
+            let lambda = sample_wavelength(rand_float());
+            let r = r.with_wavelength(Some(lambda));
+            return wavelength_to_rgb(lambda) * color(r, world, lights, depth);
+        }
+        hit.wavelength = r.wavelength();
+        hit.compute_differentials(&r);
+        let material = hit.material.clone();
+        material.apply_shading(&mut hit);
         let emitted = hit.material.emitted(&r, &hit, hit.u, hit.v, &hit.p);
-        if let Some(s_rec) = hit.material.scatter(r, &hit) {
-            if s_rec.is_specular {
-                return s_rec.attenuation
-                    * color(s_rec.specular_ray, world, lights, depth - 1);
-            } else {
-                let plight = HittablePdf::new(lights.clone(), hit.p);
-                let p = MixturePdf::new(Box::new(plight), s_rec.pdf.unwrap());
-
-                let scattered = Ray::new(hit.p, p.generate(), r.time());
-                let pdf_val = p.value(&scattered.direction());
+        let frame = hit.shading_frame();
+        let wo = frame.to_local(&-r.direction().unit());
+        let lobes = hit.material.flags(&hit);
 
+        // One-sample MIS between the lights and the material. Light sampling
+        // cannot reach specular lobes, so it is skipped for materials that
+        // have nothing else.
+        let light_prob = if lobes.has_non_specular() { 0.5 } else { 0.0 };
+        let plight = HittablePdf::new(lights.clone(), hit.p);
+        let direction = if rand_float() < light_prob {
+            plight.generate()
+        } else {
+            let bs = match hit.material.sample(&hit, &wo, (rand_float(), rand_float())) {
+                Some(bs) => bs,
+                None => return emitted,
+            };
+            let wi = frame.local_vector(&bs.wi);
+            if !hit.sides_agree(&-r.direction(), &wi) {
+                return emitted;
+            }
+            if bs.flags.is_specular() {
+                let scattered = hit
+                    .spawn_ray(wi, r.time())
+                    .with_differentials(hit.specular_differentials(&r, wi))
+                    .with_wavelength(r.wavelength());
                 return emitted
-                    + s_rec.attenuation
-                        * hit.material.scattering_pdf(&r, &hit, &scattered)
-                        * color(scattered, world, lights, depth - 1)
-                        / pdf_val;
+                    + bs.f * lobes.cos_term(&bs.wi) / (bs.pdf * (1.0 - light_prob))
+                        * color(scattered, world, lights, depth - 1);
             }
-        } else {
+            wi
+        };
+
+        let wi = frame.to_local(&direction.unit());
+        if !hit.sides_agree(&-r.direction(), &direction) {
             return emitted;
         }
+        // The lights are asked about the ray that is traced, from its offset
+        // origin, so that hashed cutouts on them decide the same both times
+        let scattered = hit
+            .spawn_ray(direction.unit(), r.time())
+            .with_wavelength(r.wavelength());
+        let light_pdf = HittablePdf::new(lights.clone(), scattered.origin());
+        let pdf_val = light_prob * light_pdf.value(&scattered.direction())
+            + (1.0 - light_prob) * hit.material.pdf(&hit, &wo, &wi);
+        if pdf_val <= 0.0 {
+            return emitted;
+        }
+        return emitted
+            + hit.material.eval(&hit, &wo, &wi)
+                * lobes.cos_term(&wi)
+                * color(scattered, world, lights, depth - 1)
+                / pdf_val;
     }
     Vec3::new(0.0, 0.0, 0.0)
 }
 
 fn main() {
     let (nx, ny, ns) = (500, 500, 1000);
+    let args: Vec<String> = std::env::args().collect();
+    // --materials FILE adds to or replaces the standard materials, and
+    // --clay renders everything but the lights in gray
+    let mut lib = MaterialLibrary::standard();
+    if let Some(i) = args.iter().position(|arg| arg == "--materials") {
+        let path = args.get(i + 1).expect("--materials needs a file");
+        match MaterialLibrary::load(path) {
+            Ok(file) => lib.extend(file),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if args.iter().any(|arg| arg == "--clay") {
+        lib = lib.with_clay();
+    }
+
+    // --wax puts a ball of wax where the glass sphere was
+    let wax = args.iter().any(|arg| arg == "--wax");
+    let aspect = ny as Float / nx as Float;
+    let (cam, world) = if wax {
+        cornell_wax(&lib, aspect)
+    } else {
+        cornell_mc(&lib, aspect)
+    };
+
+    // --pick X Y describes what is under pixel (X, Y) instead of rendering,
+    // and --pick X Y X2 Y2 also whether it sees what is under (X2, Y2)
+    if let Some(i) = args.iter().position(|arg| arg == "--pick") {
+        let coord = |k: usize| -> u32 {
+            let arg = args.get(i + k).and_then(|arg| arg.parse().ok());
+            arg.expect("--pick needs a pixel x and y")
+        };
+        let query = SceneQuery::new(&cam, &world);
+        let first = query.pick(coord(1), coord(2), nx, ny);
+        match &first {
+            Some(hit) => {
+                let name = hit.name.as_deref().unwrap_or("unnamed object");
+                let id = hit
+                    .object_id
+                    .map_or("no id".to_string(), |id| format!("id {}", id));
+                let (p, n) = (hit.point, hit.normal);
+                println!("{} ({}) at distance {}", name, id, hit.t);
+                println!("  point ({}, {}, {})", p.x(), p.y(), p.z());
+                println!("  normal ({}, {}, {})", n.x(), n.y(), n.z());
+                println!("  uv ({}, {})", hit.u, hit.v);
+                println!("  material {}", hit.material.kind());
+            }
+            None => println!("nothing"),
+        }
+        if args.get(i + 3).is_some() {
+            match (&first, query.pick(coord(3), coord(4), nx, ny)) {
+                (Some(from), Some(to)) if query.line_of_sight(from, &to) => {
+                    println!(
+                        "sees the second pick {} away",
+                        (to.point - from.point).mag()
+                    );
+                }
+                (Some(from), Some(to)) => {
+                    let toward = to.point - from.point;
+                    let blocked = query.distance(from.origin_toward(toward), toward);
+                    println!(
+                        "blocked from the second pick after {}",
+                        blocked.unwrap_or(0.0)
+                    );
+                }
+                _ => println!("nothing to see between"),
+            }
+        }
+        return;
+    }
+
     println!("P3");
     println!("{} {}", nx, ny);
     println!("255");
 
-    let (cam, world) = cornell_mc(ny as f32 / nx as f32);
+    // Flat-shaded line art for diagrams, lit from the ceiling light, with
+    // --toon N shading in N bands instead of 3
+    if let Some(i) = args.iter().position(|arg| arg == "--toon") {
+        let mut toon = ToonShader::new(Vec3::new(278.0, 554.0, 279.5));
+        if let Some(bands) = args.get(i + 1).and_then(|arg| arg.parse().ok()) {
+            toon = toon.with_bands(bands);
+        }
+        for col in toon.render(&cam, &world, nx, ny) {
+            let ir: u32 = (255.99 * col[0]) as u32;
+            let ig: u32 = (255.99 * col[1]) as u32;
+            let ib: u32 = (255.99 * col[2]) as u32;
+            println!("{} {} {}", ir, ig, ib)
+        }
+        return;
+    }
+    // Shrink pixel footprints as samples get denser, as pbrt does
+    let diff_scale = (1.0 / (ns as Float).sqrt()).max(0.125);
+    let (ds, dt) = (diff_scale / nx as Float, diff_scale / ny as Float);
 
     let light_shape: Arc<dyn Hittable> = Arc::new(XZRect::new(
         213.0,
@@ -88,23 +239,41 @@ fn main() {
         )))),
     ));
 
-    let glass = Arc::new(Dielectric::new(1.5));
-    let glass_sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(Vec3::new(190.0, 90.0, 190.0), 90.0, glass));
+    // Rays are also sent toward the glass sphere for its caustics, as long
+    // as the library's glass is purely specular there, so that light
+    // sampling cannot reach through it. Wax scatters on its own.
+    let glass_sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(
+        Vec3::new(190.0, 90.0, 190.0),
+        90.0,
+        lib.material("glass"),
+    ));
+    let top = Ray::new(
+        Vec3::new(190.0, 300.0, 190.0),
+        Vec3::new(0.0, -1.0, 0.0),
+        0.0,
+    );
+    let specular = glass_sphere
+        .hit(top, 0.0, Float::MAX)
+        .is_some_and(|hit| !hit.material.flags(&hit).has_non_specular());
 
-    let lights: Arc<dyn Hittable> = Arc::new(vec![light_shape, glass_sphere]);
+    let mut lights: Vec<Arc<dyn Hittable>> = vec![light_shape];
+    if !wax && specular {
+        lights.push(glass_sphere);
+    }
+    let lights: Arc<dyn Hittable> = Arc::new(lights);
 
     for j in (0..ny).rev() {
         for i in 0..nx {
             let mut col: Vec3 = (0..ns)
                 .into_par_iter()
                 .map(|_| {
-                    let u: f32 = (i as f32 + rand_float()) / nx as f32;
-                    let v: f32 = (j as f32 + rand_float()) / ny as f32;
-                    let r = cam.get_ray(u, v);
+                    let u: Float = (i as Float + rand_float()) / nx as Float;
+                    let v: Float = (j as Float + rand_float()) / ny as Float;
+                    let r = cam.get_ray_differential(u, v, ds, dt);
                     de_nan(&color(r, &world, &lights, 50))
                 })
                 .sum();
-            col /= ns as f32;
+            col /= ns as Float;
             col = Vec3::new(col[0].sqrt(), col[1].sqrt(), col[2].sqrt());
 
             let ir: u32 = (255.99 * col[0]) as u32;
@@ -118,6 +287,7 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use crate::util::*;
+    use crate::vec3::{consts, Float};
 
     #[test]
     fn mc() {
@@ -126,12 +296,12 @@ mod tests {
         for i in 0..n {
             let r1 = rand_float();
             let r2 = rand_float();
-            let x = (2.0 * std::f32::consts::PI * r1).cos() * 2.0 * (r2 * (1.0 - r2)).sqrt();
-            let y = (2.0 * std::f32::consts::PI * r1).sin() * 2.0 * (r2 * (1.0 - r2)).sqrt();
+            let x = (2.0 * consts::PI * r1).cos() * 2.0 * (r2 * (1.0 - r2)).sqrt();
+            let y = (2.0 * consts::PI * r1).sin() * 2.0 * (r2 * (1.0 - r2)).sqrt();
             let z = 1.0 - r2;
-            sum += z * z * z / (1.0 / (2.0 * std::f32::consts::PI));
+            sum += z * z * z / (1.0 / (2.0 * consts::PI));
         }
-        println!("Pi/2 = {}", std::f32::consts::PI / 2.0 as f32);
-        println!("Estimate = {}", sum / n as f32);
+        println!("Pi/2 = {}", consts::PI / 2.0 as Float);
+        println!("Estimate = {}", sum / n as Float);
     }
 }
@@ -1,39 +1,136 @@
-use std::sync::Arc;
+use std::ops::BitOr;
+use std::sync::{Arc, OnceLock};
 
+use crate::fresnel::*;
 use crate::hit::HitRecord;
-use crate::texture::Texture;
+use crate::microfacet::*;
+use crate::spectrum::*;
+use crate::texture::{ConstantTexture, Texture};
 use crate::util::*;
 use crate::vec3::*;
-use crate::pdf::*;
 
-pub struct ScatterRecord {
-    pub specular_ray: Ray,
-    pub is_specular: bool,
-    pub attenuation: Vec3,
-    pub pdf: Option<Box<dyn Pdf>>,
+// Lobes a material has, or the lobe a sample was drawn from
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct BsdfFlags(u8);
+
+impl BsdfFlags {
+    pub const REFLECTION: BsdfFlags = BsdfFlags(1);
+    pub const TRANSMISSION: BsdfFlags = BsdfFlags(1 << 1);
+    pub const DIFFUSE: BsdfFlags = BsdfFlags(1 << 2);
+    pub const GLOSSY: BsdfFlags = BsdfFlags(1 << 3);
+    // Delta lobes, which can be sampled but never evaluated
+    pub const SPECULAR: BsdfFlags = BsdfFlags(1 << 4);
+    // Phase functions, which have no cosine foreshortening
+    pub const VOLUME: BsdfFlags = BsdfFlags(1 << 5);
+
+    pub fn contains(self, other: BsdfFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn is_specular(self) -> bool {
+        self.contains(BsdfFlags::SPECULAR)
+    }
+    // Whether there is anything for light sampling to evaluate
+    pub fn has_non_specular(self) -> bool {
+        self.contains(BsdfFlags::DIFFUSE) || self.contains(BsdfFlags::GLOSSY)
+    }
+    // Foreshortening applied to light arriving from wi
+    pub fn cos_term(self, wi: &Vec3) -> Float {
+        if self.contains(BsdfFlags::VOLUME) {
+            1.0
+        } else {
+            wi.z().abs()
+        }
+    }
 }
 
-impl ScatterRecord {
-    pub fn new(specular_ray: Ray, is_specular: bool, attenuation: Vec3, pdf: Option<Box<dyn Pdf>>) -> Self {
-        ScatterRecord {
-            specular_ray: specular_ray,
-            is_specular: is_specular,
-            attenuation: attenuation,
+impl BitOr for BsdfFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        BsdfFlags(self.0 | rhs.0)
+    }
+}
+
+pub struct BsdfSample {
+    pub wi: Vec3,
+    pub f: Vec3,
+    pub pdf: Float,
+    pub flags: BsdfFlags,
+}
+
+impl BsdfSample {
+    pub fn new(wi: Vec3, f: Vec3, pdf: Float, flags: BsdfFlags) -> Self {
+        BsdfSample {
+            wi,
+            f,
             pdf: pdf,
+            flags,
         }
     }
 }
 
+// Directions are unit vectors in the hit's shading frame, where the normal
+// is +z, and both point away from the surface: wo toward where the light
+// goes, wi toward where it comes from. f excludes the cosine term. Specular
+// samples return f and pdf as if the delta were a density, so f * cos / pdf
+// is the sample's weight.
 pub trait Material: Sync + Send {
-    fn scatter(&self, _ray_in: Ray, _hit: &HitRecord) -> Option<ScatterRecord> {
+    // Short name of the model, e.g. "conductor", for reports of what a ray
+    // hit
+    fn kind(&self) -> &'static str;
+    fn eval(&self, _hit: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+    fn sample(&self, _hit: &HitRecord, _wo: &Vec3, _u: (Float, Float)) -> Option<BsdfSample> {
         None
     }
-    fn scattering_pdf(&self, _ray_in: &Ray, _hit: &HitRecord, _scattered: &Ray) -> f32 {
+    fn pdf(&self, _hit: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Float {
         0.0
     }
-    fn emitted(&self, _r_in: &Ray, _hit: &HitRecord, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
+    // May depend on the hit, e.g. where a roughness texture reaches zero
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::default()
+    }
+    fn emitted(&self, _r_in: &Ray, _hit: &HitRecord, _u: Float, _v: Float, _p: &Vec3) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+    // Whether scattering depends on hit.wavelength, so that the integrator
+    // must narrow the path to a single wavelength before sampling
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+    // Whether the surface is there for ray r at the hit. Primitives skip
+    // hits that fail, so cutouts hide them from every kind of ray.
+    fn alpha_test(&self, _hit: &HitRecord, _r: &Ray) -> bool {
+        true
+    }
+    // Called once the hit is complete, before scattering, to perturb its
+    // shading normal
+    fn apply_shading(&self, _hit: &mut HitRecord) {}
+}
+
+#[inline]
+pub fn same_hemisphere(w: &Vec3, wp: &Vec3) -> bool {
+    w.z() * wp.z() > 0.0
+}
+
+// Beer-Lambert attenuation over a distance through a medium with absorption
+// coefficient sigma_a
+#[inline]
+pub fn transmittance(sigma_a: Vec3, distance: Float) -> Vec3 {
+    let a = sigma_a * -distance;
+    Vec3::new(a.x().exp(), a.y().exp(), a.z().exp())
+}
+
+// Attenuation of light that reached the hit from inside an absorbing
+// object, which it crossed in a straight line from where the ray entered.
+// Rays have unit directions, so the hit's t is that distance.
+#[inline]
+fn interior_transmittance(sigma_a: Vec3, hit: &HitRecord, wo: &Vec3) -> Vec3 {
+    if wo.z() < 0.0 {
+        transmittance(sigma_a, hit.t)
+    } else {
+        Vec3::new(1.0, 1.0, 1.0)
+    }
 }
 
 // Diffuse
@@ -48,28 +145,115 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray_in: Ray, hit: &HitRecord) -> Option<ScatterRecord> {
-        let alb = self.albedo.value(hit.u, hit.v, &hit.p);
-        let pdf = Box::new(CosinePdf::new(&hit.normal));
+    fn kind(&self) -> &'static str {
+        "lambertian"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        self.albedo.filtered(hit.u, hit.v, &hit.p, &hit.footprint) / consts::PI
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        let mut wi = cosine_sample_hemisphere(u);
+        if wo.z() < 0.0 {
+            wi[2] *= -1.0;
+        }
+        let pdf = self.pdf(hit, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let flags = BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION;
+        Some(BsdfSample::new(wi, self.eval(hit, wo, &wi), pdf, flags))
+    }
+    fn pdf(&self, _hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi.z().abs() / consts::PI
+    }
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION
+    }
+}
+
+// Rough diffuse surface made of V-shaped Lambertian facets (Oren-Nayar),
+// using the qualitative model. sigma is the standard deviation of the facet
+// angle in degrees; zero is Lambertian.
+pub struct OrenNayar {
+    albedo: Arc<dyn Texture>,
+    a: Float,
+    b: Float,
+}
+
+impl OrenNayar {
+    pub fn new(albedo: Arc<dyn Texture>, sigma: Float) -> Self {
+        let sigma2 = sigma.to_radians() * sigma.to_radians();
+        Self {
+            albedo: albedo,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+}
 
-        Some(ScatterRecord::new(Ray::default(), false, alb, Some(pdf)))
+impl Material for OrenNayar {
+    fn kind(&self) -> &'static str {
+        "oren_nayar"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let flip = wo.z() < 0.0;
+        let (wo, wi) = (upper(wo, flip), upper(wi, flip));
+        let (sin_o, sin_i) = (sin2_theta(&wo).sqrt(), sin2_theta(&wi).sqrt());
+
+        // cos(phi_i - phi_o), only for directions away from the normal
+        let mut max_cos = 0.0;
+        if sin_i > 1e-4 && sin_o > 1e-4 {
+            let d_cos = cos_phi(&wi) * cos_phi(&wo) + sin_phi(&wi) * sin_phi(&wo);
+            max_cos = d_cos.max(0.0);
+        }
+        // sin(alpha) tan(beta), alpha the larger and beta the smaller angle
+        let (sin_alpha, tan_beta) = if wi.z() > wo.z() {
+            (sin_o, sin_i / wi.z())
+        } else {
+            (sin_i, sin_o / wo.z())
+        };
+        let albedo = self.albedo.filtered(hit.u, hit.v, &hit.p, &hit.footprint);
+        albedo / consts::PI * (self.a + self.b * max_cos * sin_alpha * tan_beta)
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        let mut wi = cosine_sample_hemisphere(u);
+        if wo.z() < 0.0 {
+            wi[2] *= -1.0;
+        }
+        let pdf = self.pdf(hit, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let flags = BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION;
+        Some(BsdfSample::new(wi, self.eval(hit, wo, &wi), pdf, flags))
     }
-    fn scattering_pdf(&self, _: &Ray, hit: &HitRecord, scattered: &Ray) -> f32 {
-        let cosine = dot(hit.normal, scattered.direction().unit());
-        if cosine < 0.0 {
+    fn pdf(&self, _hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        if !same_hemisphere(wo, wi) {
             return 0.0;
         }
-        cosine / std::f32::consts::PI
+        wi.z().abs() / consts::PI
+    }
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION
     }
 }
 
 pub struct Metal {
     albedo: Vec3,
-    fuzz: f32,
+    fuzz: Float,
 }
 
 impl Metal {
-    pub fn new(a: Vec3, f: f32) -> Self {
+    pub fn new(a: Vec3, f: Float) -> Self {
         let mut clamped_f = 1.0;
         if f < 1.0 {
             clamped_f = f;
@@ -79,99 +263,2588 @@ impl Metal {
             fuzz: clamped_f,
         }
     }
+
+    // Fuzz spreads reflections as GGX roughness would, with alpha at half
+    // the fuzz giving highlights about as wide as the old fuzz sphere did
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::new(0.5 * self.fuzz, 0.5 * self.fuzz)
+    }
 }
 
+// Mirror reflection tinted by the albedo, or without Fresnel a glossy GGX
+// lobe once fuzzed. Two-sided.
 impl Material for Metal {
-    fn scatter(&self, ray_in: Ray, hit: &HitRecord) -> Option<ScatterRecord> {
-        let reflected: Vec3 = reflect(ray_in.direction().unit(), hit.normal);
-        let scattered = Ray::new(
-            hit.p,
-            reflected + self.fuzz * random_in_unit_sphere(),
-            ray_in.time(),
+    fn kind(&self) -> &'static str {
+        "metal"
+    }
+    fn eval(&self, _hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if self.fuzz <= 0.0 || !same_hemisphere(wo, wi) {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let flip = wo.z() < 0.0;
+        let (wo, wi) = (upper(wo, flip), upper(wi, flip));
+        let wm = wi + wo;
+        if wm.mag_sqrd() == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let distrib = self.distribution();
+        let wm = wm.unit();
+        self.albedo * distrib.d(&wm) * distrib.g(&wo, &wi) / (4.0 * wi.z() * wo.z())
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        if wo.z() == 0.0 {
+            return None;
+        }
+        if self.fuzz <= 0.0 {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            let flags = BsdfFlags::SPECULAR | BsdfFlags::REFLECTION;
+            return Some(BsdfSample::new(wi, self.albedo / wi.z().abs(), 1.0, flags));
+        }
+        let flip = wo.z() < 0.0;
+        let wo_up = upper(wo, flip);
+        let wm = self.distribution().sample_wm(&wo_up, u);
+        let wi = upper(&reflect_about(&wo_up, &wm), flip);
+        if !same_hemisphere(wo, &wi) {
+            return None;
+        }
+        let pdf = self.pdf(hit, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let flags = BsdfFlags::GLOSSY | BsdfFlags::REFLECTION;
+        Some(BsdfSample::new(wi, self.eval(hit, wo, &wi), pdf, flags))
+    }
+    fn pdf(&self, _hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        if self.fuzz <= 0.0 || !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let flip = wo.z() < 0.0;
+        let (wo, wi) = (upper(wo, flip), upper(wi, flip));
+        let wm = wi + wo;
+        if wm.mag_sqrd() == 0.0 {
+            return 0.0;
+        }
+        let wm = wm.unit();
+        self.distribution().d_visible(&wo, &wm) / (4.0 * dot(wo, wm).abs())
+    }
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        if self.fuzz <= 0.0 {
+            BsdfFlags::SPECULAR | BsdfFlags::REFLECTION
+        } else {
+            BsdfFlags::GLOSSY | BsdfFlags::REFLECTION
+        }
+    }
+}
+
+// Metal with a complex index of refraction eta + i k per RGB channel and
+// GGX microfacet roughness. Zero roughness is a perfect mirror. Roughness
+// may differ across and along a tangent direction, as in brushed metal.
+pub struct Conductor {
+    eta: Vec3,
+    k: Vec3,
+    roughness: Arc<dyn Texture>,
+    film: Option<ThinFilm>,
+    roughness_v: Option<Arc<dyn Texture>>,
+    rotation: Float,
+    tangent_map: Option<Arc<dyn Texture>>,
+}
+
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness: Arc<dyn Texture>) -> Self {
+        Self {
+            eta,
+            k,
+            roughness,
+            film: None,
+            roughness_v: None,
+            rotation: 0.0,
+            tangent_map: None,
+        }
+    }
+    // Makes the metal anisotropic: roughness then applies along the
+    // tangent, dpdu by default, and roughness_v across it
+    pub fn with_roughness_v(mut self, roughness_v: Arc<dyn Texture>) -> Self {
+        self.roughness_v = Some(roughness_v);
+        self
+    }
+    // Turns the tangent about the normal, counterclockwise from dpdu
+    pub fn with_tangent_rotation(mut self, degrees: Float) -> Self {
+        self.rotation = degrees.to_radians();
+        self
+    }
+    // Tangents from a flow map, whose red and green give the direction
+    // along dpdu and dpdv mapped from [0, 1] to [-1, 1]
+    pub fn with_tangent_map(mut self, map: Arc<dyn Texture>) -> Self {
+        self.tangent_map = Some(map);
+        self
+    }
+    // Coats the metal with a film, as with oxide layers and tempering colors
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+    // Measured indices sampled at 650, 550 and 450nm
+    pub fn gold(roughness: Arc<dyn Texture>) -> Self {
+        Self::new(
+            Vec3::new(0.143, 0.374, 1.442),
+            Vec3::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+    pub fn silver(roughness: Arc<dyn Texture>) -> Self {
+        Self::new(
+            Vec3::new(0.155, 0.117, 0.138),
+            Vec3::new(4.828, 3.122, 2.147),
+            roughness,
+        )
+    }
+    pub fn copper(roughness: Arc<dyn Texture>) -> Self {
+        Self::new(
+            Vec3::new(0.200, 0.924, 1.102),
+            Vec3::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+    pub fn aluminium(roughness: Arc<dyn Texture>) -> Self {
+        Self::new(
+            Vec3::new(1.657, 0.880, 0.521),
+            Vec3::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    fn distribution(&self, hit: &HitRecord) -> TrowbridgeReitz {
+        let roughness = self
+            .roughness
+            .filtered(hit.u, hit.v, &hit.p, &hit.footprint)
+            .x();
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+        let alpha_v = match &self.roughness_v {
+            Some(r) => {
+                let roughness_v = r.filtered(hit.u, hit.v, &hit.p, &hit.footprint).x();
+                TrowbridgeReitz::roughness_to_alpha(roughness_v)
+            }
+            None => alpha,
+        };
+        TrowbridgeReitz::new(alpha, alpha_v)
+    }
+    // Angle of the tangent from the shading frame's x axis
+    fn tangent_angle(&self, hit: &HitRecord) -> Float {
+        match &self.tangent_map {
+            Some(map) => {
+                let d = 2.0 * map.filtered(hit.u, hit.v, &hit.p, &hit.footprint)
+                    - Vec3::new(1.0, 1.0, 1.0);
+                self.rotation + d.y().atan2(d.x())
+            }
+            None => self.rotation,
+        }
+    }
+
+    fn fresnel(&self, hit: &HitRecord, cos_i: Float) -> Vec3 {
+        match &self.film {
+            Some(film) => film.reflectance(hit, cos_i, 1.0, |lambda| {
+                Complex::new(rgb_at(self.eta, lambda), rgb_at(self.k, lambda))
+            }),
+            None => fr_conductor(cos_i, self.eta, self.k),
+        }
+    }
+}
+
+// Two-sided reflection, such as from metals, mirrors directions below the
+// surface above it
+#[inline]
+pub(crate) fn upper(w: &Vec3, flip: bool) -> Vec3 {
+    if flip {
+        Vec3::new(w.x(), w.y(), -w.z())
+    } else {
+        *w
+    }
+}
+
+// w turned by angle about the normal
+#[inline]
+fn rotate_z(w: &Vec3, angle: Float) -> Vec3 {
+    if angle == 0.0 {
+        return *w;
+    }
+    let (s, c) = angle.sin_cos();
+    Vec3::new(c * w.x() - s * w.y(), s * w.x() + c * w.y(), w.z())
+}
+
+impl Material for Conductor {
+    fn kind(&self) -> &'static str {
+        "conductor"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let distrib = self.distribution(hit);
+        if !same_hemisphere(wo, wi) || distrib.effectively_smooth() {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let flip = wo.z() < 0.0;
+        let angle = -self.tangent_angle(hit);
+        let (wo, wi) = (rotate_z(wo, angle), rotate_z(wi, angle));
+        let (wo, wi) = (upper(&wo, flip), upper(&wi, flip));
+        let wm = wi + wo;
+        if wm.mag_sqrd() == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let wm = wm.unit();
+        let fr = self.fresnel(hit, dot(wo, wm).abs());
+        fr * distrib.d(&wm) * distrib.g(&wo, &wi) / (4.0 * wi.z() * wo.z())
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        if wo.z() == 0.0 {
+            return None;
+        }
+        let distrib = self.distribution(hit);
+        let flip = wo.z() < 0.0;
+        if distrib.effectively_smooth() {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            let f = self.fresnel(hit, wi.z().abs()) / wi.z().abs();
+            let flags = BsdfFlags::SPECULAR | BsdfFlags::REFLECTION;
+            return Some(BsdfSample::new(wi, f, 1.0, flags));
+        }
+        // Sample about the tangent, then turn back
+        let angle = self.tangent_angle(hit);
+        let wo_up = upper(&rotate_z(wo, -angle), flip);
+        let wm = distrib.sample_wm(&wo_up, u);
+        let wi = rotate_z(&upper(&reflect_about(&wo_up, &wm), flip), angle);
+        if !same_hemisphere(wo, &wi) {
+            return None;
+        }
+        let pdf = self.pdf(hit, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let flags = BsdfFlags::GLOSSY | BsdfFlags::REFLECTION;
+        Some(BsdfSample::new(wi, self.eval(hit, wo, &wi), pdf, flags))
+    }
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        let distrib = self.distribution(hit);
+        if !same_hemisphere(wo, wi) || distrib.effectively_smooth() {
+            return 0.0;
+        }
+        let flip = wo.z() < 0.0;
+        let angle = -self.tangent_angle(hit);
+        let (wo, wi) = (rotate_z(wo, angle), rotate_z(wi, angle));
+        let (wo, wi) = (upper(&wo, flip), upper(&wi, flip));
+        let wm = wi + wo;
+        if wm.mag_sqrd() == 0.0 {
+            return 0.0;
+        }
+        let wm = wm.unit();
+        distrib.d_visible(&wo, &wm) / (4.0 * dot(wo, wm).abs())
+    }
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        if self.distribution(hit).effectively_smooth() {
+            BsdfFlags::SPECULAR | BsdfFlags::REFLECTION
+        } else {
+            BsdfFlags::GLOSSY | BsdfFlags::REFLECTION
+        }
+    }
+}
+
+// Transparent film a few hundred nm thick over a surface, as with soap
+// bubbles, oil slicks and anti-reflective coatings. Reflections off its two
+// sides interfere, so the surface's reflectance depends on wavelength and
+// shifts color with the viewing angle.
+pub struct ThinFilm {
+    // Film thickness in nm, from the first channel
+    thickness: Arc<dyn Texture>,
+    ior: Float,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: Arc<dyn Texture>, ior: Float) -> Self {
+        Self { thickness, ior }
+    }
+
+    // RGB reflectance of the film, seen from a medium of index n1, over a
+    // substrate whose index at each wavelength is n3
+    fn reflectance<N: Fn(Float) -> Complex>(
+        &self,
+        hit: &HitRecord,
+        cos_i: Float,
+        n1: Float,
+        n3: N,
+    ) -> Vec3 {
+        let thickness = self
+            .thickness
+            .filtered(hit.u, hit.v, &hit.p, &hit.footprint)
+            .x();
+        let thickness = thickness.max(0.0);
+        let rgb = spectrum_to_rgb(
+            |lambda| fr_thin_film(cos_i, lambda, thickness, n1, self.ior, n3(lambda)),
+            32,
         );
-        let attenuation = self.albedo;
+        rgb.max(Vec3::new(0.0, 0.0, 0.0))
+            .min(Vec3::new(1.0, 1.0, 1.0))
+    }
 
-        Some(ScatterRecord::new(scattered, true, attenuation, None))
+    // Over glass of index eta, for wo on either side. Both sides of a film
+    // between lossless media reflect alike, so from inside the film is seen
+    // between glass and air.
+    fn dielectric_reflectance(&self, hit: &HitRecord, wo: &Vec3, eta: Float) -> Vec3 {
+        if wo.z() >= 0.0 {
+            self.reflectance(hit, wo.z(), 1.0, |_| Complex::real(eta))
+        } else {
+            self.reflectance(hit, -wo.z(), eta, |_| Complex::real(1.0))
+        }
+    }
+}
+
+// Index of refraction as a function of wavelength
+#[derive(Copy, Clone, Debug)]
+pub enum Dispersion {
+    // n = a + b / lambda^2, lambda in micrometres
+    Cauchy { a: Float, b: Float },
+    // n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i), lambda in micrometres
+    Sellmeier { b: [Float; 3], c: [Float; 3] },
+}
+
+impl Dispersion {
+    // Borosilicate crown glass, Schott N-BK7
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.039612, 0.23179234, 1.0104695],
+            c: [0.0060006987, 0.020017914, 103.56065],
+        }
+    }
+    // Malitson 1965
+    pub fn fused_silica() -> Self {
+        Dispersion::Sellmeier {
+            b: [0.6961663, 0.4079426, 0.8974794],
+            c: [0.004679148, 0.013512063, 97.934003],
+        }
+    }
+    // Peter 1923
+    pub fn diamond() -> Self {
+        Dispersion::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.030625, 0.011236, 0.0],
+        }
+    }
+    // At wavelength lambda in nm
+    pub fn ior(&self, lambda: Float) -> Float {
+        let l2 = (lambda / 1000.0) * (lambda / 1000.0);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let mut n2 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * l2 / (l2 - c[i]);
+                }
+                n2.sqrt()
+            }
+        }
     }
 }
 
 pub struct Dielectric {
-    ref_idx: f32,
+    ref_idx: Float,
+    absorption: Vec3,
+    film: Option<ThinFilm>,
+    dispersion: Option<Dispersion>,
 }
 
 impl Dielectric {
-    pub fn new(ri: f32) -> Self {
-        Self { ref_idx: ri }
+    pub fn new(ri: Float) -> Self {
+        Self {
+            ref_idx: ri,
+            absorption: Vec3::new(0.0, 0.0, 0.0),
+            film: None,
+            dispersion: None,
+        }
+    }
+    // Refracts each wavelength by its own index, splitting white light into
+    // its colors. ref_idx becomes the index at 550nm, which paths that have
+    // not been given a wavelength see.
+    pub fn with_dispersion(mut self, dispersion: Dispersion) -> Self {
+        self.ref_idx = dispersion.ior(550.0);
+        self.dispersion = Some(dispersion);
+        self
+    }
+    // Coats the surface with a film whose interference colors reflections
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+    // Tints light traveling inside by exp(-sigma_a * distance), so colored
+    // glass darkens where it is thick. sigma_a is per unit of scene length.
+    pub fn with_absorption(mut self, sigma_a: Vec3) -> Self {
+        self.absorption = sigma_a;
+        self
+    }
+}
+
+impl Dielectric {
+    fn ior(&self, hit: &HitRecord) -> Float {
+        match (self.dispersion, hit.wavelength) {
+            (Some(dispersion), Some(lambda)) => dispersion.ior(lambda),
+            _ => self.ref_idx,
+        }
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: Ray, hit: &HitRecord) -> Option<ScatterRecord> {
+    fn kind(&self) -> &'static str {
+        "dielectric"
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        let tint = interior_transmittance(self.absorption, hit, wo);
+        let ref_idx = self.ior(hit);
         let outward_normal: Vec3;
-        let ni_over_nt: f32;
-        let cosine: f32;
+        let ni_over_nt: Float;
+        let cosine: Float;
 
-        if dot(ray_in.direction(), hit.normal) > 0.0 {
-            outward_normal = -1.0 * hit.normal;
-            ni_over_nt = self.ref_idx;
-            cosine = self.ref_idx * dot(ray_in.direction(), hit.normal) / ray_in.direction().mag();
+        if wo.z() < 0.0 {
+            outward_normal = Vec3::new(0.0, 0.0, -1.0);
+            ni_over_nt = ref_idx;
+            cosine = -ref_idx * wo.z();
         } else {
-            outward_normal = hit.normal;
-            ni_over_nt = 1.0 / self.ref_idx;
-            cosine = -1.0 * dot(ray_in.direction(), hit.normal) / ray_in.direction().mag();
+            outward_normal = Vec3::new(0.0, 0.0, 1.0);
+            ni_over_nt = 1.0 / ref_idx;
+            cosine = wo.z();
         }
 
-        let reflected = reflect(ray_in.direction(), hit.normal);
-        let attenuation = Vec3::new(1.0, 1.0, 1.0);
+        // Reflectance per channel, which a film makes differ
+        let refracted = refract(-*wo, outward_normal, ni_over_nt);
+        let reflectance = match (&refracted, &self.film) {
+            (None, _) => Vec3::new(1.0, 1.0, 1.0),
+            (Some(_), Some(film)) => film.dielectric_reflectance(hit, wo, ref_idx),
+            (Some(_), None) => {
+                let r = schlick(cosine, ref_idx);
+                Vec3::new(r, r, r)
+            }
+        };
+        let reflect_prob = (reflectance.x() + reflectance.y() + reflectance.z()) / 3.0;
 
-        if let Some(refracted) = refract(ray_in.direction(), outward_normal, ni_over_nt) {
-            let refract_prob = 1.0 - schlick(cosine, self.ref_idx);
-            if rand_float() < refract_prob {
-                let scattering = Ray::new(hit.p, refracted, ray_in.time());
-                return Some(ScatterRecord::new(scattering, true, attenuation, None));
+        if let Some(refracted) = refracted {
+            if u.0 >= reflect_prob {
+                let wi = refracted.unit();
+                let one = Vec3::new(1.0, 1.0, 1.0);
+                let f = tint * (one - reflectance) / wi.z().abs();
+                let flags = BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION;
+                return Some(BsdfSample::new(wi, f, 1.0 - reflect_prob, flags));
             }
         }
-        let scattering = Ray::new(hit.p, reflected, ray_in.time());
-        Some(ScatterRecord::new(scattering, true, attenuation, None))
+        let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+        let f = tint * reflectance / wi.z().abs();
+        let flags = BsdfFlags::SPECULAR | BsdfFlags::REFLECTION;
+        Some(BsdfSample::new(wi, f, reflect_prob, flags))
+    }
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::SPECULAR | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
+    }
+    fn is_dispersive(&self) -> bool {
+        self.dispersion.is_some()
     }
 }
 
-pub struct DiffuseLight {
-    emit: Arc<dyn Texture>,
+// Glass too thin to model as a solid, such as a window pane or a soap
+// bubble, on a single surface. Light bounces between its two faces, which
+// sums to a reflectance of R + T^2 R / (1 - R^2) for the reflectance R and
+// transmittance T = 1 - R of one face, and passes through without bending.
+pub struct ThinDielectric {
+    ref_idx: Float,
 }
 
-impl DiffuseLight {
-    pub fn new(a: Arc<dyn Texture>) -> Self {
-        Self { emit: a }
+impl ThinDielectric {
+    pub fn new(ri: Float) -> Self {
+        Self { ref_idx: ri }
+    }
+    fn reflectance(&self, cos_i: Float) -> Float {
+        let r = fr_dielectric(cos_i.abs(), self.ref_idx);
+        if r < 1.0 {
+            let t = 1.0 - r;
+            r + t * t * r / (1.0 - r * r)
+        } else {
+            r
+        }
     }
 }
 
-impl Material for DiffuseLight {
-    fn scatter(&self, _ray_in: Ray, _hit: &HitRecord) -> Option<ScatterRecord> {
-        None
+impl Material for ThinDielectric {
+    fn kind(&self) -> &'static str {
+        "thin_dielectric"
     }
-    fn emitted(&self, r_in: &Ray, hit: &HitRecord, u: f32, v: f32, p: &Vec3) -> Vec3 {
-        if dot(hit.normal, r_in.direction()) < 0.0 {
-            return self.emit.value(u, v, p);
+    fn sample(&self, _hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        if wo.z() == 0.0 {
+            return None;
         }
-        Vec3::new(0.0, 0.0, 0.0)
+        let r = self.reflectance(wo.z());
+        if u.0 < r {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            let flags = BsdfFlags::SPECULAR | BsdfFlags::REFLECTION;
+            return Some(BsdfSample::new(
+                wi,
+                Vec3::new(r, r, r) / wi.z().abs(),
+                r,
+                flags,
+            ));
+        }
+        let wi = -*wo;
+        let t = 1.0 - r;
+        let flags = BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION;
+        Some(BsdfSample::new(
+            wi,
+            Vec3::new(t, t, t) / wi.z().abs(),
+            t,
+            flags,
+        ))
+    }
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::SPECULAR | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
     }
 }
 
-pub struct Isotropic {
-    albedo: Arc<dyn Texture>,
+// Frosted glass: GGX microfacet reflection and transmission (Walter et al.
+// 2007) with exact dielectric Fresnel. Zero roughness is smooth glass.
+pub struct RoughDielectric {
+    ref_idx: Float,
+    roughness: Arc<dyn Texture>,
+    absorption: Vec3,
 }
 
-impl Isotropic {
-    pub fn new(a: Arc<dyn Texture>) -> Self {
-        Self { albedo: a }
+impl RoughDielectric {
+    pub fn new(ri: Float, roughness: Arc<dyn Texture>) -> Self {
+        Self {
+            ref_idx: ri,
+            roughness,
+            absorption: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+    // As for Dielectric
+    pub fn with_absorption(mut self, sigma_a: Vec3) -> Self {
+        self.absorption = sigma_a;
+        self
+    }
+
+    fn distribution(&self, hit: &HitRecord) -> TrowbridgeReitz {
+        let roughness = self
+            .roughness
+            .filtered(hit.u, hit.v, &hit.p, &hit.footprint)
+            .x();
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+        TrowbridgeReitz::new(alpha, alpha)
     }
 }
 
-impl Material for Isotropic {
-    fn scatter(&self, _ray_in: Ray, hit: &HitRecord) -> Option<ScatterRecord> {
-        let scattered = Ray::new(hit.p, random_in_unit_sphere(), hit.t);
-        let attenuation = self.albedo.value(hit.u, hit.v, &hit.p);
-        Some(ScatterRecord::new(scattered, false, attenuation, None))
+// Generalized half vector of a pair of directions, facing +z, and the
+// relative eta across it. None where no microfacet can connect them.
+fn dielectric_half_vector(eta: Float, wo: &Vec3, wi: &Vec3) -> Option<(Vec3, Float)> {
+    let (cos_o, cos_i) = (wo.z(), wi.z());
+    if cos_o == 0.0 || cos_i == 0.0 {
+        return None;
+    }
+    let etap = if cos_o * cos_i > 0.0 {
+        1.0
+    } else if cos_o > 0.0 {
+        eta
+    } else {
+        1.0 / eta
+    };
+    let wm = *wi * etap + *wo;
+    if wm.mag_sqrd() == 0.0 {
+        return None;
+    }
+    let wm = if wm.z() < 0.0 { -wm.unit() } else { wm.unit() };
+    // Backfacing microfacets
+    if dot(wm, *wi) * cos_i < 0.0 || dot(wm, *wo) * cos_o < 0.0 {
+        return None;
+    }
+    Some((wm, etap))
+}
+
+// The rough dielectric lobe on its own, shared with materials that layer it
+// under others. The distribution must not be effectively smooth.
+fn rough_dielectric_eval(distrib: &TrowbridgeReitz, eta: Float, wo: &Vec3, wi: &Vec3) -> Float {
+    let (wm, etap) = match dielectric_half_vector(eta, wo, wi) {
+        Some(h) => h,
+        None => return 0.0,
+    };
+    let fr = fr_dielectric(dot(*wo, wm), eta);
+    if same_hemisphere(wo, wi) {
+        distrib.d(&wm) * distrib.g(wo, wi) * fr / (4.0 * wi.z() * wo.z()).abs()
+    } else {
+        let denom = (dot(*wi, wm) + dot(*wo, wm) / etap).powi(2) * wi.z() * wo.z();
+        let jacobian = (dot(*wi, wm) * dot(*wo, wm) / denom).abs();
+        distrib.d(&wm) * (1.0 - fr) * distrib.g(wo, wi) * jacobian
+    }
+}
+
+fn rough_dielectric_pdf(distrib: &TrowbridgeReitz, eta: Float, wo: &Vec3, wi: &Vec3) -> Float {
+    let (wm, etap) = match dielectric_half_vector(eta, wo, wi) {
+        Some(h) => h,
+        None => return 0.0,
+    };
+    let r = fr_dielectric(dot(*wo, wm), eta);
+    if same_hemisphere(wo, wi) {
+        distrib.d_visible(wo, &wm) / (4.0 * dot(*wo, wm).abs()) * r
+    } else {
+        let denom = (dot(*wi, wm) + dot(*wo, wm) / etap).powi(2);
+        let dwm_dwi = dot(*wi, wm).abs() / denom;
+        distrib.d_visible(wo, &wm) * dwm_dwi * (1.0 - r)
+    }
+}
+
+// Picks a visible microfacet, then reflects or refracts through it in
+// proportion to its Fresnel reflectance. The low digits of u.0 barely move
+// the microfacet, so they make that choice.
+fn rough_dielectric_sample(
+    distrib: &TrowbridgeReitz,
+    eta: Float,
+    wo: &Vec3,
+    u: (Float, Float),
+) -> Option<(Vec3, BsdfFlags)> {
+    let wm = distrib.sample_wm(wo, u);
+    let r = fr_dielectric(dot(*wo, wm), eta);
+    if (u.0 * 1024.0).fract() < r {
+        let wi = reflect_about(wo, &wm);
+        if !same_hemisphere(wo, &wi) {
+            return None;
+        }
+        Some((wi, BsdfFlags::GLOSSY | BsdfFlags::REFLECTION))
+    } else {
+        let (wi, _) = refract_about(wo, &wm, eta)?;
+        if same_hemisphere(wo, &wi) || wi.z() == 0.0 {
+            return None;
+        }
+        Some((wi, BsdfFlags::GLOSSY | BsdfFlags::TRANSMISSION))
+    }
+}
+
+impl Material for RoughDielectric {
+    fn kind(&self) -> &'static str {
+        "rough_dielectric"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let distrib = self.distribution(hit);
+        if distrib.effectively_smooth() {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let f = rough_dielectric_eval(&distrib, self.ref_idx, wo, wi);
+        interior_transmittance(self.absorption, hit, wo) * f
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        if wo.z() == 0.0 {
+            return None;
+        }
+        let distrib = self.distribution(hit);
+        if distrib.effectively_smooth() {
+            let tint = interior_transmittance(self.absorption, hit, wo);
+            let r = fr_dielectric(wo.z(), self.ref_idx);
+            if u.0 < r {
+                let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+                let f = tint * r / wi.z().abs();
+                let flags = BsdfFlags::SPECULAR | BsdfFlags::REFLECTION;
+                return Some(BsdfSample::new(wi, f, r, flags));
+            }
+            let (wi, _) = refract_about(wo, &Vec3::new(0.0, 0.0, 1.0), self.ref_idx)?;
+            let f = tint * (1.0 - r) / wi.z().abs();
+            let flags = BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION;
+            return Some(BsdfSample::new(wi, f, 1.0 - r, flags));
+        }
+
+        let (wi, flags) = rough_dielectric_sample(&distrib, self.ref_idx, wo, u)?;
+        let pdf = self.pdf(hit, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample::new(wi, self.eval(hit, wo, &wi), pdf, flags))
+    }
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        let distrib = self.distribution(hit);
+        if distrib.effectively_smooth() {
+            return 0.0;
+        }
+        rough_dielectric_pdf(&distrib, self.ref_idx, wo, wi)
+    }
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        let lobe = if self.distribution(hit).effectively_smooth() {
+            BsdfFlags::SPECULAR
+        } else {
+            BsdfFlags::GLOSSY
+        };
+        lobe | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
+    }
+}
+
+// Disney-style principled BSDF with the Blender Principled parameter set.
+// Lobes are a Burley diffuse with sheen, a GGX specular whose color goes
+// from a tinted dielectric to the base color with metallic, a GTR1
+// clearcoat and a rough dielectric transmission tinted by the base color.
+// Every parameter is a texture read from its first channel, except the
+// base color.
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    metallic: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    specular: Arc<dyn Texture>,
+    specular_tint: Arc<dyn Texture>,
+    sheen: Arc<dyn Texture>,
+    sheen_tint: Arc<dyn Texture>,
+    clearcoat: Arc<dyn Texture>,
+    clearcoat_gloss: Arc<dyn Texture>,
+    transmission: Arc<dyn Texture>,
+    ior: Float,
+}
+
+// Principled parameters evaluated at a hit
+struct PrincipledParams {
+    base: Vec3,
+    metallic: Float,
+    roughness: Float,
+    specular: Float,
+    specular_tint: Float,
+    sheen: Float,
+    sheen_tint: Float,
+    clearcoat: Float,
+    clearcoat_gloss: Float,
+    transmission: Float,
+    ior: Float,
+}
+
+fn constant_texture(v: Float) -> Arc<dyn Texture> {
+    Arc::new(ConstantTexture::new(Vec3::new(v, v, v)))
+}
+
+#[inline]
+fn lerp(t: Float, a: Float, b: Float) -> Float {
+    (1.0 - t) * a + t * b
+}
+
+#[inline]
+fn schlick_weight(cos: Float) -> Float {
+    (1.0 - cos).clamp(0.0, 1.0).powi(5)
+}
+
+// Hue and saturation of a color at unit luminance
+fn tint(c: Vec3) -> Vec3 {
+    let lum = 0.3 * c.x() + 0.6 * c.y() + 0.1 * c.z();
+    if lum > 0.0 {
+        c / lum
+    } else {
+        Vec3::new(1.0, 1.0, 1.0)
+    }
+}
+
+// Generalized Trowbridge-Reitz with gamma 1, the long tailed clearcoat
+// distribution, normalized so that D * cos integrates to one
+fn gtr1(cos_h: Float, a: Float) -> Float {
+    if a >= 1.0 {
+        return consts::FRAC_1_PI;
+    }
+    let a2 = a * a;
+    let t = 1.0 + (a2 - 1.0) * cos_h * cos_h;
+    (a2 - 1.0) / (consts::PI * a2.ln() * t)
+}
+
+fn sample_gtr1(a: Float, u: (Float, Float)) -> Vec3 {
+    let a2 = a * a;
+    let cos_h = ((1.0 - a2.powf(1.0 - u.0)) / (1.0 - a2)).max(0.0).sqrt();
+    let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+    let phi = 2.0 * consts::PI * u.1;
+    Vec3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h)
+}
+
+// Smith masking for GGX, divided by 2 cos
+fn smith_g_ggx(cos: Float, alpha: Float) -> Float {
+    let (a, b) = (alpha * alpha, cos * cos);
+    1.0 / (cos + (a + b - a * b).sqrt())
+}
+
+impl Principled {
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        Self {
+            base_color,
+            metallic: constant_texture(0.0),
+            roughness: constant_texture(0.5),
+            specular: constant_texture(0.5),
+            specular_tint: constant_texture(0.0),
+            sheen: constant_texture(0.0),
+            sheen_tint: constant_texture(0.5),
+            clearcoat: constant_texture(0.0),
+            clearcoat_gloss: constant_texture(1.0),
+            transmission: constant_texture(0.0),
+            ior: 1.5,
+        }
+    }
+    pub fn with_metallic(mut self, t: Arc<dyn Texture>) -> Self {
+        self.metallic = t;
+        self
+    }
+    pub fn with_roughness(mut self, t: Arc<dyn Texture>) -> Self {
+        self.roughness = t;
+        self
+    }
+    pub fn with_specular(mut self, t: Arc<dyn Texture>) -> Self {
+        self.specular = t;
+        self
+    }
+    pub fn with_specular_tint(mut self, t: Arc<dyn Texture>) -> Self {
+        self.specular_tint = t;
+        self
+    }
+    pub fn with_sheen(mut self, t: Arc<dyn Texture>) -> Self {
+        self.sheen = t;
+        self
+    }
+    pub fn with_sheen_tint(mut self, t: Arc<dyn Texture>) -> Self {
+        self.sheen_tint = t;
+        self
+    }
+    pub fn with_clearcoat(mut self, t: Arc<dyn Texture>) -> Self {
+        self.clearcoat = t;
+        self
+    }
+    pub fn with_clearcoat_gloss(mut self, t: Arc<dyn Texture>) -> Self {
+        self.clearcoat_gloss = t;
+        self
+    }
+    pub fn with_transmission(mut self, t: Arc<dyn Texture>) -> Self {
+        self.transmission = t;
+        self
+    }
+    pub fn with_ior(mut self, ior: Float) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    fn params(&self, hit: &HitRecord) -> PrincipledParams {
+        let scalar = |t: &Arc<dyn Texture>| {
+            t.filtered(hit.u, hit.v, &hit.p, &hit.footprint)
+                .x()
+                .clamp(0.0, 1.0)
+        };
+        PrincipledParams {
+            base: self
+                .base_color
+                .filtered(hit.u, hit.v, &hit.p, &hit.footprint),
+            metallic: scalar(&self.metallic),
+            roughness: scalar(&self.roughness),
+            specular: scalar(&self.specular),
+            specular_tint: scalar(&self.specular_tint),
+            sheen: scalar(&self.sheen),
+            sheen_tint: scalar(&self.sheen_tint),
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_gloss: scalar(&self.clearcoat_gloss),
+            transmission: scalar(&self.transmission),
+            ior: self.ior,
+        }
+    }
+}
+
+impl PrincipledParams {
+    fn distribution(&self) -> TrowbridgeReitz {
+        let alpha = TrowbridgeReitz::roughness_to_alpha(self.roughness).max(1e-3);
+        TrowbridgeReitz::new(alpha, alpha)
+    }
+    fn clearcoat_alpha(&self) -> Float {
+        lerp(self.clearcoat_gloss, 0.1, 0.001)
+    }
+    fn transmission_weight(&self) -> Float {
+        (1.0 - self.metallic) * self.transmission
+    }
+    // Probabilities of sampling the diffuse, specular, clearcoat and
+    // transmission lobes. From inside a transmissive object only the
+    // dielectric boundary is seen.
+    fn lobe_probs(&self, inside: bool) -> [Float; 4] {
+        let trans = self.transmission_weight();
+        if inside && trans > 0.0 {
+            return [0.0, 0.0, 0.0, 1.0];
+        }
+        let w = [
+            (1.0 - self.metallic) * (1.0 - self.transmission),
+            1.0 - trans,
+            0.25 * self.clearcoat,
+            trans,
+        ];
+        let sum: Float = w.iter().sum();
+        [w[0] / sum, w[1] / sum, w[2] / sum, w[3] / sum]
+    }
+    // Opaque surfaces are two-sided, so directions below them are mirrored
+    // above. Transmissive ones keep their sides.
+    fn flip(&self, wo: &Vec3) -> bool {
+        wo.z() < 0.0 && self.transmission_weight() == 0.0
+    }
+
+    // Reflection lobes for wo and wi above the surface
+    fn eval_reflection(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let wh = *wi + *wo;
+        if wh.mag_sqrd() == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let wh = wh.unit();
+        let cos_d = dot(*wi, wh);
+        let c_tint = tint(self.base);
+        let mut f = Vec3::new(0.0, 0.0, 0.0);
+
+        let diffuse_w = (1.0 - self.metallic) * (1.0 - self.transmission);
+        if diffuse_w > 0.0 {
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let fl = 1.0 + (fd90 - 1.0) * schlick_weight(wi.z());
+            let fv = 1.0 + (fd90 - 1.0) * schlick_weight(wo.z());
+            let white = Vec3::new(1.0, 1.0, 1.0);
+            let sheen_color = white * (1.0 - self.sheen_tint) + c_tint * self.sheen_tint;
+            let sheen = sheen_color * self.sheen * schlick_weight(cos_d);
+            f += diffuse_w * (self.base * fl * fv / consts::PI + sheen);
+        }
+
+        let spec_w = 1.0 - self.transmission_weight();
+        if spec_w > 0.0 {
+            let distrib = self.distribution();
+            let white = Vec3::new(1.0, 1.0, 1.0);
+            let dielectric = (white * (1.0 - self.specular_tint) + c_tint * self.specular_tint)
+                * (0.08 * self.specular);
+            let f0 = dielectric * (1.0 - self.metallic) + self.base * self.metallic;
+            let fr = f0 + (white - f0) * schlick_weight(cos_d);
+            f += spec_w * fr * distrib.d(&wh) * distrib.g(wo, wi) / (4.0 * wi.z() * wo.z());
+        }
+
+        if self.clearcoat > 0.0 {
+            let d = gtr1(wh.z(), self.clearcoat_alpha());
+            let fr = lerp(schlick_weight(cos_d), 0.04, 1.0);
+            let g = smith_g_ggx(wi.z(), 0.25) * smith_g_ggx(wo.z(), 0.25);
+            let c = 0.25 * self.clearcoat * d * fr * g;
+            f += Vec3::new(c, c, c);
+        }
+        f
+    }
+
+    // Densities of the lobes for wo above the surface, in lobe_probs order
+    fn pdf_reflection(&self, wo: &Vec3, wi: &Vec3) -> [Float; 3] {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return [0.0; 3];
+        }
+        let wh = (*wi + *wo).unit();
+        let spec = self.distribution().d_visible(wo, &wh) / (4.0 * dot(*wo, wh).abs());
+        let coat = gtr1(wh.z(), self.clearcoat_alpha()) * wh.z() / (4.0 * dot(*wo, wh).abs());
+        [wi.z() / consts::PI, spec, coat]
+    }
+
+    fn eval_transmission(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let f = rough_dielectric_eval(&self.distribution(), self.ior, wo, wi);
+        if same_hemisphere(wo, wi) {
+            Vec3::new(f, f, f)
+        } else {
+            self.base * f
+        }
+    }
+}
+
+impl Material for Principled {
+    fn kind(&self) -> &'static str {
+        "principled"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let p = self.params(hit);
+        let trans = p.transmission_weight();
+        if wo.z() < 0.0 && trans > 0.0 {
+            return p.eval_transmission(wo, wi);
+        }
+        let flip = p.flip(wo);
+        let f = p.eval_reflection(&upper(wo, flip), &upper(wi, flip));
+        if trans > 0.0 {
+            return f + trans * p.eval_transmission(wo, wi);
+        }
+        f
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        if wo.z() == 0.0 {
+            return None;
+        }
+        let p = self.params(hit);
+        let probs = p.lobe_probs(wo.z() < 0.0);
+        let flip = p.flip(wo);
+        let wo_up = upper(wo, flip);
+
+        // Choose a lobe with the first dimension and stretch what is left of
+        // it back over [0, 1)
+        let mut lobe = 0;
+        let mut u0 = u.0;
+        while lobe < 3 && u0 >= probs[lobe] {
+            u0 -= probs[lobe];
+            lobe += 1;
+        }
+        let u = ((u0 / probs[lobe]).min(1.0 - Float::EPSILON), u.1);
+
+        let (wi, flags) = match lobe {
+            0 => (
+                cosine_sample_hemisphere(u),
+                BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+            ),
+            1 => {
+                let wm = p.distribution().sample_wm(&wo_up, u);
+                (
+                    reflect_about(&wo_up, &wm),
+                    BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+                )
+            }
+            2 => {
+                let wh = sample_gtr1(p.clearcoat_alpha(), u);
+                (
+                    reflect_about(&wo_up, &wh),
+                    BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+                )
+            }
+            _ => rough_dielectric_sample(&p.distribution(), self.ior, wo, u)?,
+        };
+        let wi = if lobe < 3 { upper(&wi, flip) } else { wi };
+        if lobe < 3 && !same_hemisphere(wo, &wi) {
+            return None;
+        }
+        let pdf = self.pdf(hit, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample::new(wi, self.eval(hit, wo, &wi), pdf, flags))
+    }
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        let p = self.params(hit);
+        let probs = p.lobe_probs(wo.z() < 0.0);
+        let flip = p.flip(wo);
+        let refl = p.pdf_reflection(&upper(wo, flip), &upper(wi, flip));
+        let mut pdf = probs[0] * refl[0] + probs[1] * refl[1] + probs[2] * refl[2];
+        if probs[3] > 0.0 {
+            pdf += probs[3] * rough_dielectric_pdf(&p.distribution(), self.ior, wo, wi);
+        }
+        pdf
+    }
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        let p = self.params(hit);
+        let mut flags = BsdfFlags::GLOSSY | BsdfFlags::REFLECTION;
+        if (1.0 - p.metallic) * (1.0 - p.transmission) > 0.0 {
+            flags = flags | BsdfFlags::DIFFUSE;
+        }
+        if p.transmission_weight() > 0.0 {
+            flags = flags | BsdfFlags::TRANSMISSION;
+        }
+        flags
+    }
+}
+
+// A dielectric coat over any base material, as in lacquered wood or glossy
+// plastic. Light not reflected by the coat refracts into it, is attenuated
+// by the coat's absorption on the way down and back up, and scatters off the
+// base. Light the coat reflects back onto the base is accounted for in
+// closed form from how much of the base's reflection the coat traps, which
+// is exact for a Lambertian base (Weidlich and Wilkie 2007). The layer is opaque and
+// two-sided.
+pub struct Coated {
+    base: Arc<dyn Material>,
+    ref_idx: Float,
+    roughness: Option<Arc<dyn Texture>>,
+    thickness: Float,
+    absorption: Vec3,
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>, ref_idx: Float) -> Self {
+        Self {
+            base,
+            ref_idx,
+            roughness: None,
+            thickness: 0.0,
+            absorption: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+    // GGX roughness of the coat's surface; the coat is smooth without one
+    pub fn with_roughness(mut self, roughness: Arc<dyn Texture>) -> Self {
+        self.roughness = Some(roughness);
+        self
+    }
+    // Absorption coefficient sigma_a per unit length, and how thick the
+    // coat is in the same units
+    pub fn with_absorption(mut self, sigma_a: Vec3, thickness: Float) -> Self {
+        self.absorption = sigma_a;
+        self.thickness = thickness;
+        self
+    }
+
+    fn distribution(&self, hit: &HitRecord) -> Option<TrowbridgeReitz> {
+        let roughness = self.roughness.as_ref()?;
+        let r = roughness.filtered(hit.u, hit.v, &hit.p, &hit.footprint).x();
+        let alpha = TrowbridgeReitz::roughness_to_alpha(r);
+        let distrib = TrowbridgeReitz::new(alpha, alpha);
+        if distrib.effectively_smooth() {
+            None
+        } else {
+            Some(distrib)
+        }
+    }
+
+    // Direction inside the coat that w outside refracts to, pointing up
+    fn enter_layer(&self, w: &Vec3) -> Option<Vec3> {
+        let (t, _) = refract_about(w, &Vec3::new(0.0, 0.0, 1.0), self.ref_idx)?;
+        Some(-t)
+    }
+    // Inverse of enter_layer, None where the coat reflects it back inside
+    fn exit_layer(&self, w: &Vec3) -> Option<Vec3> {
+        let (t, _) = refract_about(&-*w, &Vec3::new(0.0, 0.0, 1.0), self.ref_idx)?;
+        Some(t)
+    }
+
+    // Fraction of light arriving along the normal that the base reflects
+    // and the coat then reflects back down, from a fixed set of the base's
+    // own samples
+    fn trapped(&self, hit: &HitRecord) -> Vec3 {
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        // Hammersley points, which are evenly spread in each dimension on
+        // its own and so resolve the coat's critical angle whichever one a
+        // sampler maps to elevation
+        let n: u32 = 32;
+        let mut trapped = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let u = (
+                (i as Float + 0.5) / n as Float,
+                (i.reverse_bits() as Float / 4294967296.0) + 0.5 / n as Float,
+            );
+            if let Some(bs) = self.base.sample(hit, &up, u) {
+                if bs.wi.z() > 0.0 && bs.pdf > 0.0 {
+                    let weight = bs.f * bs.flags.cos_term(&bs.wi) / bs.pdf;
+                    trapped += weight * fr_dielectric(-bs.wi.z(), self.ref_idx);
+                }
+            }
+        }
+        (trapped / n as Float)
+            .max(Vec3::new(0.0, 0.0, 0.0))
+            .min(Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    // Scales the base's response at wo_l, wi_l inside the coat to what is
+    // seen outside at wo, wi
+    fn layer_scale(&self, trapped: Vec3, wo: &Vec3, wi: &Vec3, wo_l: &Vec3, wi_l: &Vec3) -> Vec3 {
+        let t_o = 1.0 - fr_dielectric(wo.z(), self.ref_idx);
+        let t_i = 1.0 - fr_dielectric(wi.z(), self.ref_idx);
+        let path = self.thickness * (1.0 / wo_l.z() + 1.0 / wi_l.z());
+        let transmittance = transmittance(self.absorption, path);
+
+        let one = Vec3::new(1.0, 1.0, 1.0);
+        let bounces = one / (one - trapped * transmittance);
+
+        transmittance * bounces * (t_o * t_i / (self.ref_idx * self.ref_idx))
+    }
+
+    // Probability of sampling the coat rather than the base, kept away
+    // from zero and one so neither goes unsampled
+    fn coat_prob(&self, wo: &Vec3) -> Float {
+        fr_dielectric(wo.z(), self.ref_idx).clamp(0.1, 0.9)
+    }
+}
+
+impl Material for Coated {
+    fn kind(&self) -> &'static str {
+        "coated"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let flip = wo.z() < 0.0;
+        let (wo, wi) = (upper(wo, flip), upper(wi, flip));
+
+        let mut f = match self.distribution(hit) {
+            Some(distrib) => {
+                let c = rough_dielectric_eval(&distrib, self.ref_idx, &wo, &wi);
+                Vec3::new(c, c, c)
+            }
+            None => Vec3::new(0.0, 0.0, 0.0),
+        };
+        if let (Some(wo_l), Some(wi_l)) = (self.enter_layer(&wo), self.enter_layer(&wi)) {
+            let trapped = self.trapped(hit);
+            let base = self.base.eval(hit, &wo_l, &wi_l);
+            f += base * self.layer_scale(trapped, &wo, &wi, &wo_l, &wi_l);
+        }
+        f
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        if wo.z() == 0.0 {
+            return None;
+        }
+        let flip = wo.z() < 0.0;
+        let wo_up = upper(wo, flip);
+        let p_coat = self.coat_prob(&wo_up);
+        let distrib = self.distribution(hit);
+
+        if u.0 < p_coat {
+            let u = (u.0 / p_coat, u.1);
+            let distrib = match distrib {
+                Some(distrib) => distrib,
+                None => {
+                    let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+                    let fr = fr_dielectric(wo_up.z(), self.ref_idx);
+                    let f = Vec3::new(fr, fr, fr) / wi.z().abs();
+                    let flags = BsdfFlags::SPECULAR | BsdfFlags::REFLECTION;
+                    return Some(BsdfSample::new(wi, f, p_coat, flags));
+                }
+            };
+            let wm = distrib.sample_wm(&wo_up, u);
+            let wi = upper(&reflect_about(&wo_up, &wm), flip);
+            if !same_hemisphere(wo, &wi) {
+                return None;
+            }
+            let pdf = self.pdf(hit, wo, &wi);
+            if pdf <= 0.0 {
+                return None;
+            }
+            let flags = BsdfFlags::GLOSSY | BsdfFlags::REFLECTION;
+            return Some(BsdfSample::new(wi, self.eval(hit, wo, &wi), pdf, flags));
+        }
+
+        let u = ((u.0 - p_coat) / (1.0 - p_coat), u.1);
+        let wo_l = self.enter_layer(&wo_up)?;
+        let bs = self.base.sample(hit, &wo_l, u)?;
+        if bs.wi.z() <= 0.0 {
+            return None;
+        }
+        let wi_up = self.exit_layer(&bs.wi)?;
+        let wi = upper(&wi_up, flip);
+        if bs.flags.is_specular() {
+            // Refracted through the coat's Jacobian into the outside solid angle
+            let trapped = self.trapped(hit);
+            let scale = self.layer_scale(trapped, &wo_up, &wi_up, &wo_l, &bs.wi);
+            let jacobian = wi_up.z() / (self.ref_idx * self.ref_idx * bs.wi.z());
+            let pdf = (1.0 - p_coat) * bs.pdf * jacobian;
+            return Some(BsdfSample::new(wi, bs.f * scale, pdf, bs.flags));
+        }
+        let pdf = self.pdf(hit, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample::new(wi, self.eval(hit, wo, &wi), pdf, bs.flags))
+    }
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let flip = wo.z() < 0.0;
+        let (wo, wi) = (upper(wo, flip), upper(wi, flip));
+        let p_coat = self.coat_prob(&wo);
+
+        let mut pdf = 0.0;
+        if let Some(distrib) = self.distribution(hit) {
+            let wm = (wo + wi).unit();
+            pdf += p_coat * distrib.d_visible(&wo, &wm) / (4.0 * dot(wo, wm).abs());
+        }
+        if let (Some(wo_l), Some(wi_l)) = (self.enter_layer(&wo), self.enter_layer(&wi)) {
+            let jacobian = wi.z() / (self.ref_idx * self.ref_idx * wi_l.z());
+            pdf += (1.0 - p_coat) * self.base.pdf(hit, &wo_l, &wi_l) * jacobian;
+        }
+        pdf
+    }
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        let coat = if self.distribution(hit).is_some() {
+            BsdfFlags::GLOSSY
+        } else {
+            BsdfFlags::SPECULAR
+        };
+        self.base.flags(hit) | coat | BsdfFlags::REFLECTION
+    }
+}
+
+// Charlie sheen distribution of fibers (Estevez and Kulla 2017)
+#[inline]
+fn charlie_d(alpha: Float, cos_h: Float) -> Float {
+    let inv = 1.0 / alpha;
+    let sin2 = (1.0 - cos_h * cos_h).max(0.0);
+    (2.0 + inv) * sin2.powf(0.5 * inv) / (2.0 * consts::PI)
+}
+
+// Sheen BRDF with Neubelt and Pettineo's visibility term, for wo and wi
+// above the surface
+fn charlie_sheen(alpha: Float, wo: &Vec3, wi: &Vec3) -> Float {
+    let (cos_o, cos_i) = (wo.z(), wi.z());
+    if cos_o <= 0.0 || cos_i <= 0.0 {
+        return 0.0;
+    }
+    let wh = (*wo + *wi).unit();
+    charlie_d(alpha, wh.z()) / (4.0 * (cos_i + cos_o - cos_i * cos_o))
+}
+
+fn sheen_alpha(roughness: Float) -> Float {
+    TrowbridgeReitz::roughness_to_alpha(roughness).max(1e-3)
+}
+
+const SHEEN_TABLE_SIZE: usize = 16;
+
+// Directional albedo of a white sheen lobe, integrated on first use over a
+// grid of cos_o and roughness and interpolated bilinearly
+fn sheen_albedo(cos_o: Float, roughness: Float) -> Float {
+    static TABLE: OnceLock<Vec<Float>> = OnceLock::new();
+    let n = SHEEN_TABLE_SIZE;
+    let table = TABLE.get_or_init(|| {
+        let m = 64;
+        let mut table = Vec::with_capacity(n * n);
+        for j in 0..n {
+            let alpha = sheen_alpha(j as Float / (n - 1) as Float);
+            for i in 0..n {
+                let cos_o = (i as Float / (n - 1) as Float).max(1e-3);
+                let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+                // Stratified uniform samples of the hemisphere
+                let mut sum = 0.0;
+                for a in 0..m {
+                    for b in 0..m {
+                        let z = (a as Float + 0.5) / m as Float;
+                        let phi = 2.0 * consts::PI * (b as Float + 0.5) / m as Float;
+                        let r = (1.0 - z * z).sqrt();
+                        let wi = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                        sum += charlie_sheen(alpha, &wo, &wi) * z;
+                    }
+                }
+                table.push(sum * 2.0 * consts::PI / (m * m) as Float);
+            }
+        }
+        table
+    });
+    let x = cos_o.clamp(0.0, 1.0) * (n - 1) as Float;
+    let y = roughness.clamp(0.0, 1.0) * (n - 1) as Float;
+    let (i, j) = ((x as usize).min(n - 2), (y as usize).min(n - 2));
+    let (dx, dy) = (x - i as Float, y - j as Float);
+    let at = |i: usize, j: usize| table[i + n * j];
+    (1.0 - dx) * (1.0 - dy) * at(i, j)
+        + dx * (1.0 - dy) * at(i + 1, j)
+        + (1.0 - dx) * dy * at(i, j + 1)
+        + dx * dy * at(i + 1, j + 1)
+}
+
+// Retroreflective, grazing sheen of cloth and velvet from the Charlie
+// distribution of fibers. On its own it is a thin layer of fibers; over a
+// base, e.g. Lambertian, the base only receives the light the sheen does
+// not reflect (scaled by the sheen's tabulated albedo, as in Filament).
+// Two-sided.
+pub struct Sheen {
+    color: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    base: Option<Arc<dyn Material>>,
+}
+
+impl Sheen {
+    pub fn new(color: Arc<dyn Texture>, roughness: Arc<dyn Texture>) -> Self {
+        Self {
+            color,
+            roughness,
+            base: None,
+        }
+    }
+    pub fn with_base(mut self, base: Arc<dyn Material>) -> Self {
+        self.base = Some(base);
+        self
+    }
+    fn params(&self, hit: &HitRecord) -> (Vec3, Float) {
+        let color = self.color.filtered(hit.u, hit.v, &hit.p, &hit.footprint);
+        let roughness = self
+            .roughness
+            .filtered(hit.u, hit.v, &hit.p, &hit.footprint)
+            .x();
+        (color, roughness.clamp(0.0, 1.0))
+    }
+    // Fraction of light reaching the base from wo
+    fn base_scale(&self, color: Vec3, roughness: Float, wo: &Vec3) -> Float {
+        (1.0 - color.max_component() * sheen_albedo(wo.z().abs(), roughness)).max(0.0)
+    }
+    // Probability of sampling the sheen rather than the base
+    fn sheen_prob(&self, color: Vec3, roughness: Float, wo: &Vec3) -> Float {
+        match self.base {
+            Some(_) => {
+                (color.max_component() * sheen_albedo(wo.z().abs(), roughness)).clamp(0.1, 0.9)
+            }
+            None => 1.0,
+        }
+    }
+    fn eval_sheen(&self, color: Vec3, roughness: Float, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let flip = wo.z() < 0.0;
+        let (wo, wi) = (upper(wo, flip), upper(wi, flip));
+        color * charlie_sheen(sheen_alpha(roughness), &wo, &wi)
+    }
+}
+
+impl Material for Sheen {
+    fn kind(&self) -> &'static str {
+        "sheen"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let (color, roughness) = self.params(hit);
+        let sheen = self.eval_sheen(color, roughness, wo, wi);
+        match &self.base {
+            Some(base) => sheen + base.eval(hit, wo, wi) * self.base_scale(color, roughness, wo),
+            None => sheen,
+        }
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        if wo.z() == 0.0 {
+            return None;
+        }
+        let (color, roughness) = self.params(hit);
+        let prob = self.sheen_prob(color, roughness, wo);
+        let wi = if u.0 < prob {
+            // Uniform over the hemisphere, which the broad lobe covers
+            let z = (u.0 / prob).min(1.0 - Float::EPSILON);
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let phi = 2.0 * consts::PI * u.1;
+            let z = if wo.z() < 0.0 { -z } else { z };
+            Vec3::new(r * phi.cos(), r * phi.sin(), z)
+        } else {
+            let base = self.base.as_ref()?;
+            let u0 = ((u.0 - prob) / (1.0 - prob)).min(1.0 - Float::EPSILON);
+            let bs = base.sample(hit, wo, (u0, u.1))?;
+            if bs.flags.is_specular() {
+                let scale = self.base_scale(color, roughness, wo);
+                let pdf = bs.pdf * (1.0 - prob);
+                return Some(BsdfSample::new(bs.wi, bs.f * scale, pdf, bs.flags));
+            }
+            bs.wi
+        };
+        let pdf = self.pdf(hit, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let flags = BsdfFlags::GLOSSY | BsdfFlags::REFLECTION;
+        Some(BsdfSample::new(wi, self.eval(hit, wo, &wi), pdf, flags))
+    }
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        let (color, roughness) = self.params(hit);
+        let prob = self.sheen_prob(color, roughness, wo);
+        let sheen = if same_hemisphere(wo, wi) {
+            1.0 / (2.0 * consts::PI)
+        } else {
+            0.0
+        };
+        match &self.base {
+            Some(base) => prob * sheen + (1.0 - prob) * base.pdf(hit, wo, wi),
+            None => sheen,
+        }
+    }
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        let sheen = BsdfFlags::GLOSSY | BsdfFlags::REFLECTION;
+        match &self.base {
+            Some(base) => sheen | base.flags(hit),
+            None => sheen,
+        }
+    }
+}
+
+// Blend of two materials by a weight texture, 0 giving a and 1 giving b,
+// e.g. rust patches over polished steel. Scattering and emission are the
+// weighted sums of both; samples are drawn from one chosen by its weight.
+pub struct Mix {
+    a: Arc<dyn Material>,
+    b: Arc<dyn Material>,
+    weight: Arc<dyn Texture>,
+}
+
+impl Mix {
+    pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: Arc<dyn Texture>) -> Self {
+        Self { a, b, weight }
+    }
+    fn weight(&self, hit: &HitRecord) -> Float {
+        let w = self.weight.filtered(hit.u, hit.v, &hit.p, &hit.footprint);
+        ((w.x() + w.y() + w.z()) / 3.0).clamp(0.0, 1.0)
+    }
+}
+
+impl Material for Mix {
+    fn kind(&self) -> &'static str {
+        "mix"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let w = self.weight(hit);
+        (1.0 - w) * self.a.eval(hit, wo, wi) + w * self.b.eval(hit, wo, wi)
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        let w = self.weight(hit);
+        // Remap u.0 so the chosen material sees a fresh uniform sample
+        let (chosen, prob, u0) = if u.0 < w {
+            (&self.b, w, u.0 / w)
+        } else {
+            (&self.a, 1.0 - w, (u.0 - w) / (1.0 - w))
+        };
+        let bs = chosen.sample(hit, wo, (u0.min(1.0 - Float::EPSILON), u.1))?;
+        if bs.flags.is_specular() {
+            // The other material has no density along a delta
+            return Some(BsdfSample::new(bs.wi, bs.f * prob, bs.pdf * prob, bs.flags));
+        }
+        let f = self.eval(hit, wo, &bs.wi);
+        let pdf = self.pdf(hit, wo, &bs.wi);
+        Some(BsdfSample::new(bs.wi, f, pdf, bs.flags))
+    }
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        let w = self.weight(hit);
+        (1.0 - w) * self.a.pdf(hit, wo, wi) + w * self.b.pdf(hit, wo, wi)
+    }
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        let w = self.weight(hit);
+        if w == 0.0 {
+            self.a.flags(hit)
+        } else if w == 1.0 {
+            self.b.flags(hit)
+        } else {
+            self.a.flags(hit) | self.b.flags(hit)
+        }
+    }
+    fn emitted(&self, r_in: &Ray, hit: &HitRecord, u: Float, v: Float, p: &Vec3) -> Vec3 {
+        let w = self.weight(hit);
+        (1.0 - w) * self.a.emitted(r_in, hit, u, v, p) + w * self.b.emitted(r_in, hit, u, v, p)
+    }
+    fn is_dispersive(&self) -> bool {
+        self.a.is_dispersive() || self.b.is_dispersive()
+    }
+}
+
+// Makes parts of a material transparent by an opacity texture, e.g. leaves
+// on foliage cards or decals. Rays pass through with probability one minus
+// the opacity, decided by a hash of the ray so that the same ray always
+// agrees, or wherever the opacity is below a threshold if one is set.
+pub struct Cutout {
+    material: Arc<dyn Material>,
+    opacity: Arc<dyn Texture>,
+    use_alpha: bool,
+    threshold: Option<Float>,
+}
+
+impl Cutout {
+    // Opacity from the texture's value, like other scalar textures
+    pub fn new(material: Arc<dyn Material>, opacity: Arc<dyn Texture>) -> Self {
+        Self {
+            material,
+            opacity,
+            use_alpha: false,
+            threshold: None,
+        }
+    }
+    // Opacity from the texture's alpha channel, e.g. an RGBA ImageTexture
+    pub fn from_alpha(material: Arc<dyn Material>, texture: Arc<dyn Texture>) -> Self {
+        Self {
+            use_alpha: true,
+            ..Self::new(material, texture)
+        }
+    }
+    pub fn with_threshold(mut self, threshold: Float) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+    fn opacity(&self, hit: &HitRecord) -> Float {
+        if self.use_alpha {
+            self.opacity.alpha(hit.u, hit.v, &hit.p)
+        } else {
+            self.opacity.value(hit.u, hit.v, &hit.p).x()
+        }
+    }
+}
+
+impl Material for Cutout {
+    fn kind(&self) -> &'static str {
+        "cutout"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        self.material.eval(hit, wo, wi)
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        self.material.sample(hit, wo, u)
+    }
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        self.material.pdf(hit, wo, wi)
+    }
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        self.material.flags(hit)
+    }
+    fn emitted(&self, r_in: &Ray, hit: &HitRecord, u: Float, v: Float, p: &Vec3) -> Vec3 {
+        self.material.emitted(r_in, hit, u, v, p)
+    }
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+    fn apply_shading(&self, hit: &mut HitRecord) {
+        self.material.apply_shading(hit)
+    }
+    fn alpha_test(&self, hit: &HitRecord, r: &Ray) -> bool {
+        let opacity = self.opacity(hit);
+        match self.threshold {
+            Some(threshold) => opacity >= threshold,
+            None => {
+                opacity >= 1.0 || (opacity > 0.0 && hash_float(r.origin(), r.direction()) < opacity)
+            }
+        }
+    }
+}
+
+// Shades a material with normals from a tangent space normal map, whose
+// red, green and blue give the normal along dpdu, dpdv and the surface
+// normal, mapped from [0, 1] to [-1, 1]
+pub struct NormalMap {
+    material: Arc<dyn Material>,
+    map: Arc<dyn Texture>,
+}
+
+impl NormalMap {
+    pub fn new(material: Arc<dyn Material>, map: Arc<dyn Texture>) -> Self {
+        Self { material, map }
+    }
+}
+
+// Shades a material as if its surface were displaced along the normal by a
+// scalar height texture times scale, in scene units
+pub struct BumpMap {
+    material: Arc<dyn Material>,
+    bump: Arc<dyn Texture>,
+    scale: Float,
+}
+
+impl BumpMap {
+    pub fn new(material: Arc<dyn Material>, bump: Arc<dyn Texture>, scale: Float) -> Self {
+        Self {
+            material,
+            bump,
+            scale,
+        }
+    }
+    fn height(&self, u: Float, v: Float, p: &Vec3) -> Float {
+        self.scale * self.bump.value(u, v, p).x()
+    }
+}
+
+impl Material for NormalMap {
+    fn kind(&self) -> &'static str {
+        "normal_map"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        self.material.eval(hit, wo, wi)
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        self.material.sample(hit, wo, u)
+    }
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        self.material.pdf(hit, wo, wi)
+    }
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        self.material.flags(hit)
+    }
+    fn emitted(&self, r_in: &Ray, hit: &HitRecord, u: Float, v: Float, p: &Vec3) -> Vec3 {
+        self.material.emitted(r_in, hit, u, v, p)
+    }
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+    fn alpha_test(&self, hit: &HitRecord, r: &Ray) -> bool {
+        self.material.alpha_test(hit, r)
+    }
+    fn apply_shading(&self, hit: &mut HitRecord) {
+        self.material.apply_shading(hit);
+        let n = hit.shading_normal();
+        let (t, b) = hit.tangents(n);
+        let m = 2.0 * self.map.filtered(hit.u, hit.v, &hit.p, &hit.footprint)
+            - Vec3::new(1.0, 1.0, 1.0);
+        let perturbed = m.x() * t + m.y() * b + m.z() * n;
+        if perturbed.mag_sqrd() > 0.0 {
+            hit.set_shading_normal(perturbed);
+        }
+    }
+}
+
+impl Material for BumpMap {
+    fn kind(&self) -> &'static str {
+        "bump_map"
+    }
+    fn eval(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        self.material.eval(hit, wo, wi)
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        self.material.sample(hit, wo, u)
+    }
+    fn pdf(&self, hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        self.material.pdf(hit, wo, wi)
+    }
+    fn flags(&self, hit: &HitRecord) -> BsdfFlags {
+        self.material.flags(hit)
+    }
+    fn emitted(&self, r_in: &Ray, hit: &HitRecord, u: Float, v: Float, p: &Vec3) -> Vec3 {
+        self.material.emitted(r_in, hit, u, v, p)
+    }
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+    fn alpha_test(&self, hit: &HitRecord, r: &Ray) -> bool {
+        self.material.alpha_test(hit, r)
+    }
+    // Finite differences of the height over half the pixel footprint, or a
+    // small fixed step without one, tilt the tangents (pbrt's bump mapping)
+    fn apply_shading(&self, hit: &mut HitRecord) {
+        self.material.apply_shading(hit);
+        let n = hit.shading_normal();
+        let (t, b) = hit.tangents(n);
+        let (dpdu, dpdv) = if hit.dpdu.mag_sqrd() > 0.0 && hit.dpdv.mag_sqrd() > 0.0 {
+            (hit.dpdu, hit.dpdv)
+        } else {
+            (t, b)
+        };
+        let fp = hit.footprint;
+        let step = |d: Float| if d > 0.0 { d } else { 5e-4 };
+        let du = step(0.5 * (fp.dudx.abs() + fp.dudy.abs()));
+        let dv = step(0.5 * (fp.dvdx.abs() + fp.dvdy.abs()));
+        let h = self.height(hit.u, hit.v, &hit.p);
+        let h_u = self.height(hit.u + du, hit.v, &(hit.p + du * dpdu));
+        let h_v = self.height(hit.u, hit.v + dv, &(hit.p + dv * dpdv));
+        let bumped_u = dpdu + (h_u - h) / du * n;
+        let bumped_v = dpdv + (h_v - h) / dv * n;
+        let perturbed = cross(bumped_u, bumped_v);
+        if perturbed.mag_sqrd() > 0.0 {
+            // Keep the side of the unperturbed normal
+            let perturbed = if dot(perturbed, n) < 0.0 {
+                -perturbed
+            } else {
+                perturbed
+            };
+            hit.set_shading_normal(perturbed);
+        }
+    }
+}
+
+// Lambertian emitter whose radiance is its texture's color times a scale.
+// Emits from the front of the surface only unless two-sided.
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture>,
+    strength: Strength,
+    two_sided: bool,
+}
+
+// How bright a light is, either as a scale on its texture or as a total
+// power over its area, which depends on how many sides emit
+#[derive(Copy, Clone)]
+enum Strength {
+    Scale(Float),
+    Power { watts: Float, area: Float },
+}
+
+// Luminous efficacy of 555nm light, in lumens per watt
+const LUMENS_PER_WATT: Float = 683.0;
+
+impl DiffuseLight {
+    pub fn new(a: Arc<dyn Texture>) -> Self {
+        Self {
+            emit: a,
+            strength: Strength::Scale(1.0),
+            two_sided: false,
+        }
+    }
+    // White light of a blackbody's color at temperature kelvin
+    pub fn blackbody(kelvin: Float) -> Self {
+        Self::new(Arc::new(ConstantTexture::new(blackbody_rgb(kelvin))))
+    }
+    pub fn with_two_sided(mut self, two_sided: bool) -> Self {
+        self.two_sided = two_sided;
+        self
+    }
+    pub fn with_scale(mut self, scale: Float) -> Self {
+        self.strength = Strength::Scale(scale);
+        self
+    }
+    // Scales the light so that a surface of the given area in scene units
+    // squared emits watts in total from all its emitting sides, taking the
+    // texture as a tint of unit luminance, e.g. a blackbody color
+    pub fn with_power(mut self, watts: Float, area: Float) -> Self {
+        self.strength = Strength::Power { watts, area };
+        self
+    }
+    // As with_power, for a luminous flux in lumens
+    pub fn with_lumens(self, lumens: Float, area: Float) -> Self {
+        self.with_power(lumens / LUMENS_PER_WATT, area)
+    }
+
+    fn scale(&self) -> Float {
+        match self.strength {
+            Strength::Scale(scale) => scale,
+            Strength::Power { watts, area } => {
+                let sides = if self.two_sided { 2.0 } else { 1.0 };
+                watts / (consts::PI * area * sides)
+            }
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn kind(&self) -> &'static str {
+        "light"
+    }
+    fn emitted(&self, r_in: &Ray, hit: &HitRecord, u: Float, v: Float, p: &Vec3) -> Vec3 {
+        if self.two_sided || dot(hit.normal, r_in.direction()) < 0.0 {
+            return self.scale() * self.emit.filtered(u, v, p, &hit.footprint);
+        }
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+}
+
+// Phase function of a medium scattering equally in all directions
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(a: Arc<dyn Texture>) -> Self {
+        Self { albedo: a }
+    }
+}
+
+impl Material for Isotropic {
+    fn kind(&self) -> &'static str {
+        "isotropic"
+    }
+    fn eval(&self, hit: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Vec3 {
+        self.albedo.filtered(hit.u, hit.v, &hit.p, &hit.footprint) / (4.0 * consts::PI)
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        let wi = uniform_sample_sphere(u);
+        let flags = BsdfFlags::DIFFUSE | BsdfFlags::VOLUME;
+        Some(BsdfSample::new(
+            wi,
+            self.eval(hit, wo, &wi),
+            self.pdf(hit, wo, &wi),
+            flags,
+        ))
+    }
+    fn pdf(&self, _hit: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Float {
+        1.0 / (4.0 * consts::PI)
+    }
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::DIFFUSE | BsdfFlags::VOLUME
+    }
+}
+
+// Per channel extinction and single scattering albedo of the medium a
+// subsurface random walk travels through. Each step draws its length from
+// one channel chosen uniformly and is weighted by the balance heuristic over
+// the three, which keeps media whose channels differ unbiased.
+#[derive(Copy, Clone, Debug)]
+pub struct RandomWalk {
+    sigma_t: Vec3,
+    albedo: Vec3,
+}
+
+impl RandomWalk {
+    // mean_free_path is per channel, in scene units
+    pub fn new(mean_free_path: Vec3, albedo: Vec3) -> Self {
+        Self {
+            sigma_t: Vec3::new(1.0, 1.0, 1.0) / mean_free_path,
+            albedo,
+        }
+    }
+    pub fn sample_distance(&self, u: (Float, Float)) -> Float {
+        let channel = ((u.0 * 3.0) as u32).min(2);
+        -(1.0 - u.1).ln() / self.sigma_t[channel]
+    }
+    // Throughput of a step that scattered at distance t
+    fn scatter_weight(&self, t: Float) -> Vec3 {
+        let density = transmittance(self.sigma_t, t) * self.sigma_t;
+        let pdf = (density.x() + density.y() + density.z()) / 3.0;
+        density * self.albedo / pdf
+    }
+    // Throughput of a step that reached the boundary at distance t
+    fn escape_weight(&self, t: Float) -> Vec3 {
+        let tr = transmittance(self.sigma_t, t);
+        tr / ((tr.x() + tr.y() + tr.z()) / 3.0)
+    }
+}
+
+// Smooth boundary of an object filled with a random walk medium. Light
+// leaving the object is weighted by the walk's last step, which ended at
+// the hit, so the object must be a SubsurfaceMedium for hit.t to be that
+// step's length.
+pub struct Subsurface {
+    walk: RandomWalk,
+    ref_idx: Float,
+}
+
+impl Subsurface {
+    pub fn new(walk: RandomWalk, ref_idx: Float) -> Self {
+        Self { walk, ref_idx }
+    }
+}
+
+impl Material for Subsurface {
+    fn kind(&self) -> &'static str {
+        "subsurface"
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        let weight = if wo.z() < 0.0 {
+            self.walk.escape_weight(hit.t)
+        } else {
+            Vec3::new(1.0, 1.0, 1.0)
+        };
+        let r = fr_dielectric(wo.z(), self.ref_idx);
+        if u.0 >= r {
+            if let Some((wi, _)) = refract_about(wo, &Vec3::new(0.0, 0.0, 1.0), self.ref_idx) {
+                let f = weight * (1.0 - r) / wi.z().abs();
+                let flags = BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION;
+                return Some(BsdfSample::new(wi, f, 1.0 - r, flags));
+            }
+        }
+        let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+        let f = weight * r / wi.z().abs();
+        let flags = BsdfFlags::SPECULAR | BsdfFlags::REFLECTION;
+        Some(BsdfSample::new(wi, f, r, flags))
+    }
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::SPECULAR | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
+    }
+}
+
+// Isotropic scattering inside a SubsurfaceMedium, weighted by the walk's
+// step that ended at the hit
+pub struct SubsurfacePhase {
+    walk: RandomWalk,
+}
+
+impl SubsurfacePhase {
+    pub fn new(walk: RandomWalk) -> Self {
+        Self { walk }
+    }
+}
+
+impl Material for SubsurfacePhase {
+    fn kind(&self) -> &'static str {
+        "subsurface_phase"
+    }
+    fn eval(&self, hit: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Vec3 {
+        self.walk.scatter_weight(hit.t) / (4.0 * consts::PI)
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        let wi = uniform_sample_sphere(u);
+        let flags = BsdfFlags::DIFFUSE | BsdfFlags::VOLUME;
+        Some(BsdfSample::new(
+            wi,
+            self.eval(hit, wo, &wi),
+            self.pdf(hit, wo, &wi),
+            flags,
+        ))
+    }
+    fn pdf(&self, _hit: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Float {
+        1.0 / (4.0 * consts::PI)
+    }
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::DIFFUSE | BsdfFlags::VOLUME
+    }
+}
+
+// Fixtures for the tests of materials here and in other modules
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // A hit at the origin facing +z, made of material
+    pub fn hit_with(material: Arc<dyn Material>) -> HitRecord {
+        HitRecord::new(
+            1.0,
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            0.5,
+            0.5,
+            material,
+        )
+    }
+
+    // Checks that non-specular samples agree with eval and pdf, that pdf
+    // integrates to at most one and that no energy is created, for light
+    // arriving from a few fixed directions on both sides. Seeded, so that a
+    // failure reproduces.
+    pub fn check_bsdf(material: Arc<dyn Material>) {
+        let mut rng = StdRng::seed_from_u64(1);
+        let hit = hit_with(material.clone());
+        let n = 2000;
+        let directions = [
+            Vec3::new(0.436, 0.0, 0.9),
+            Vec3::new(0.566, 0.755, 0.33),
+            Vec3::new(-0.2, 0.68, -0.705),
+        ];
+        for wo in directions.iter() {
+            let wo = wo.unit();
+            let mut pdf_integral = 0.0;
+            let mut albedo = Vec3::new(0.0, 0.0, 0.0);
+            for _ in 0..n {
+                let u = (rng.gen::<Float>(), rng.gen::<Float>());
+
+                // Half uniform over the sphere and half from the material,
+                // so that peaked lobes are integrated as well as wide ones
+                let uniform = 0.25 / consts::PI;
+                let wi = uniform_sample_sphere((rng.gen(), rng.gen()));
+                let pdf = material.pdf(&hit, &wo, &wi);
+                pdf_integral += pdf / (0.5 * uniform + 0.5 * pdf) * 0.5 / n as Float;
+
+                let bs = match material.sample(&hit, &wo, u) {
+                    Some(bs) => bs,
+                    None => continue,
+                };
+                assert!((bs.wi.mag() - 1.0).abs() < 1e-3, "wi is not unit length");
+                albedo += bs.f * bs.flags.cos_term(&bs.wi) / bs.pdf / n as Float;
+                if bs.flags.is_specular() {
+                    continue;
+                }
+                pdf_integral += bs.pdf / (0.5 * uniform + 0.5 * bs.pdf) * 0.5 / n as Float;
+                let f = material.eval(&hit, &wo, &bs.wi);
+                let pdf = material.pdf(&hit, &wo, &bs.wi);
+                assert!(
+                    (f - bs.f).mag() <= 1e-3 * (1.0 + f.mag()),
+                    "{:?} != {:?}",
+                    f,
+                    bs.f
+                );
+                assert!(
+                    (pdf - bs.pdf).abs() <= 1e-3 * (1.0 + pdf),
+                    "{} != {}",
+                    pdf,
+                    bs.pdf
+                );
+            }
+            assert!(
+                pdf_integral < 1.05,
+                "pdf integrates to {} at {:?}",
+                pdf_integral,
+                wo
+            );
+            assert!(
+                albedo.max_component() < 1.05,
+                "albedo {:?} at {:?}",
+                albedo,
+                wo
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+    use crate::texture::ConstantTexture;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn constant(v: Float) -> Arc<dyn Texture> {
+        Arc::new(ConstantTexture::new(Vec3::new(v, v, v)))
+    }
+
+    #[test]
+    fn lambertian() {
+        check_bsdf(Arc::new(Lambertian::new(constant(0.9))));
+    }
+
+    #[test]
+    fn metal() {
+        check_bsdf(Arc::new(Metal::new(Vec3::new(0.9, 0.8, 0.7), 0.3)));
+
+        // Unfuzzed metal is a tinted mirror; fuzzed metal has a density for
+        // light sampling to weigh against
+        let tint = Vec3::new(0.9, 0.8, 0.7);
+        let mirror = Metal::new(tint, 0.0);
+        let hit = hit_with(Arc::new(Metal::new(tint, 0.0)));
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let bs = mirror.sample(&hit, &wo, (0.3, 0.7)).unwrap();
+        assert!(bs.flags.is_specular());
+        assert!((bs.wi - Vec3::new(-0.6, 0.0, 0.8)).mag() < 1e-6);
+        assert!((bs.f * bs.wi.z() / bs.pdf - tint).mag() < 1e-6);
+        let fuzzed = Metal::new(tint, 0.2);
+        assert!(fuzzed.flags(&hit).has_non_specular());
+        assert!(fuzzed.pdf(&hit, &wo, &Vec3::new(-0.6, 0.0, 0.8)) > 1.0);
+        let a = fuzzed.sample(&hit, &wo, (0.3, 0.7)).unwrap();
+        let b = fuzzed.sample(&hit, &wo, (0.3, 0.7)).unwrap();
+        assert!((a.wi - b.wi).mag() == 0.0);
+    }
+
+    #[test]
+    fn oren_nayar() {
+        check_bsdf(Arc::new(OrenNayar::new(constant(0.9), 20.0)));
+
+        // Zero roughness is Lambertian
+        let lambert: Arc<dyn Material> = Arc::new(Lambertian::new(constant(0.5)));
+        let smooth: Arc<dyn Material> = Arc::new(OrenNayar::new(constant(0.5), 0.0));
+        let hit = hit_with(smooth.clone());
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let wi = Vec3::new(-0.3, 0.4, 0.866).unit();
+        assert!((smooth.eval(&hit, &wo, &wi) - lambert.eval(&hit, &wo, &wi)).mag() < 1e-5);
+    }
+
+    #[test]
+    fn conductor() {
+        check_bsdf(Arc::new(Conductor::gold(constant(0.5))));
+
+        // Smooth metal mirrors with the Fresnel reflectance as its weight, and
+        // nearly smooth metal has to approach it
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let smooth = Conductor::gold(constant(0.0));
+        let fr = fr_conductor(0.8, smooth.eta, smooth.k);
+        for roughness in [0.0, 0.05].iter() {
+            let gold: Arc<dyn Material> = Arc::new(Conductor::gold(constant(*roughness)));
+            let hit = hit_with(gold.clone());
+            let bs = gold.sample(&hit, &wo, (0.3, 0.7)).unwrap();
+            assert_eq!(bs.flags.is_specular(), *roughness == 0.0);
+            assert!((bs.wi - Vec3::new(-0.6, 0.0, 0.8)).mag() < 0.01);
+            let weight = bs.f * bs.wi.z() / bs.pdf;
+            assert!((weight - fr).mag() < 0.01, "{:?} != {:?}", weight, fr);
+        }
+    }
+
+    #[test]
+    fn conductor_fresnel() {
+        // Normal incidence reflectance ((n-1)^2 + k^2) / ((n+1)^2 + k^2)
+        let (n, k) = (0.2, 3.9);
+        let r0 = ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+        let fr = fr_conductor(1.0, Vec3::new(n, n, n), Vec3::new(k, k, k));
+        assert!((fr.x() - r0).abs() < 1e-4, "{} != {}", fr.x(), r0);
+        assert!((fr_conductor(0.0, Vec3::new(n, n, n), Vec3::new(k, k, k)).x() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn dielectric() {
+        check_bsdf(Arc::new(Dielectric::new(1.5)));
+    }
+
+    #[test]
+    fn dielectric_absorption() {
+        // Light leaving through the far side of 2 units of glass is tinted
+        // by exp(-2 sigma_a); light entering is not
+        let sigma_a = Vec3::new(0.1, 0.5, 1.0);
+        let materials: [Arc<dyn Material>; 2] = [
+            Arc::new(Dielectric::new(1.5).with_absorption(sigma_a)),
+            Arc::new(RoughDielectric::new(1.5, constant(0.0)).with_absorption(sigma_a)),
+        ];
+        for material in materials.iter() {
+            let mut hit = hit_with(material.clone());
+            hit.t = 2.0;
+            for &(wo, tint) in [
+                (Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 1.0, 1.0)),
+                (Vec3::new(0.0, 0.0, -1.0), transmittance(sigma_a, 2.0)),
+            ]
+            .iter()
+            {
+                let bs = material.sample(&hit, &wo, (0.99, 0.5)).unwrap();
+                assert!(bs.flags.contains(BsdfFlags::TRANSMISSION));
+                let weight = bs.f * bs.wi.z().abs() / bs.pdf;
+                assert!((weight - tint).mag() < 1e-4, "{:?} != {:?}", weight, tint);
+            }
+        }
+    }
+
+    #[test]
+    fn thin_film() {
+        // Without a film the Airy sum is plain Fresnel reflectance
+        for &cos in [1.0, 0.6, 0.2].iter() {
+            let bare = fr_thin_film(cos, 550.0, 0.0, 1.0, 1.33, Complex::real(1.5));
+            assert!((bare - fr_dielectric(cos, 1.5)).abs() < 1e-4);
+            let metal = Complex::new(0.2, 3.9);
+            let bare = fr_thin_film(cos, 550.0, 0.0, 1.0, 1.33, metal);
+            assert!((bare - fr_complex(cos, metal)).abs() < 1e-4);
+        }
+
+        // A quarter wave coating of index sqrt(1.5) cancels reflection at
+        // its design wavelength but not an octave away
+        let n = (1.5 as Float).sqrt();
+        let d = 550.0 / (4.0 * n);
+        assert!(fr_thin_film(1.0, 550.0, d, 1.0, n, Complex::real(1.5)) < 1e-4);
+        assert!(fr_thin_film(1.0, 400.0, d, 1.0, n, Complex::real(1.5)) > 1e-3);
+
+        // Film colors still conserve energy and change with angle
+        let film = || ThinFilm::new(constant(300.0), 1.33);
+        let bubble: Arc<dyn Material> = Arc::new(Dielectric::new(1.0).with_thin_film(film()));
+        check_bsdf(bubble);
+        let metal = Conductor::gold(constant(0.3)).with_thin_film(film());
+        check_bsdf(Arc::new(metal));
+        let hit = hit_with(Arc::new(Lambertian::new(constant(0.5))));
+        let near = film().dielectric_reflectance(&hit, &Vec3::new(0.0, 0.0, 1.0), 1.5);
+        let far = film().dielectric_reflectance(&hit, &Vec3::new(0.8, 0.0, 0.6), 1.5);
+        assert!((near / near.max_component() - far / far.max_component()).mag() > 0.05);
+    }
+
+    #[test]
+    fn dispersion() {
+        // Catalogue indices at the sodium d line
+        assert!((Dispersion::bk7().ior(587.6) - 1.5168).abs() < 1e-3);
+        assert!((Dispersion::fused_silica().ior(587.6) - 1.4585).abs() < 1e-3);
+        assert!((Dispersion::diamond().ior(587.6) - 2.417).abs() < 5e-3);
+        let cauchy = Dispersion::Cauchy {
+            a: 1.5046,
+            b: 0.0042,
+        };
+        assert!(cauchy.ior(450.0) > cauchy.ior(650.0));
+
+        // Blue bends more than red
+        let glass: Arc<dyn Material> =
+            Arc::new(Dielectric::new(1.0).with_dispersion(Dispersion::bk7()));
+        assert!(glass.is_dispersive());
+        check_bsdf(glass.clone());
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let refracted = |lambda| {
+            let mut hit = hit_with(glass.clone());
+            hit.wavelength = Some(lambda);
+            let bs = glass.sample(&hit, &wo, (0.99, 0.5)).unwrap();
+            assert!(bs.flags.contains(BsdfFlags::TRANSMISSION));
+            bs.wi
+        };
+        assert!(refracted(450.0).x() > refracted(650.0).x());
+    }
+
+    #[test]
+    fn random_walk() {
+        // A step toward a boundary at distance d either scatters before it
+        // or escapes, and on average each channel must see its own
+        // scattering albedo and transmittance however the channels differ
+        let walk = RandomWalk::new(Vec3::new(1.0, 0.25, 0.05), Vec3::new(0.9, 0.6, 0.3));
+        let d = 0.3;
+        let n = 200000;
+        let (mut scattered, mut escaped) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        for _ in 0..n {
+            let t = walk.sample_distance((rand_float(), rand_float()));
+            if t < d {
+                scattered += walk.scatter_weight(t) / n as Float;
+            } else {
+                escaped += walk.escape_weight(d) / n as Float;
+            }
+        }
+        let tr = transmittance(walk.sigma_t, d);
+        let expected = walk.albedo * (Vec3::new(1.0, 1.0, 1.0) - tr);
+        assert!(
+            (scattered - expected).mag() < 0.02,
+            "{:?} {:?}",
+            scattered,
+            expected
+        );
+        assert!((escaped - tr).mag() < 0.02, "{:?} {:?}", escaped, tr);
+
+        // Light entering the object is reflected or refracted without loss
+        let surface: Arc<dyn Material> = Arc::new(Subsurface::new(walk, 1.4));
+        let hit = hit_with(surface.clone());
+        for &wo in [Vec3::new(0.6, 0.0, 0.8), Vec3::new(0.0, 0.99, 0.141)].iter() {
+            for _ in 0..1000 {
+                let bs = surface
+                    .sample(&hit, &wo.unit(), (rand_float(), rand_float()))
+                    .unwrap();
+                let weight = bs.f * bs.wi.z().abs() / bs.pdf;
+                assert!(
+                    (weight - Vec3::new(1.0, 1.0, 1.0)).mag() < 1e-4,
+                    "{:?}",
+                    weight
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mix() {
+        let rust = || -> Arc<dyn Material> { Arc::new(Lambertian::new(constant(0.4))) };
+        let steel = || -> Arc<dyn Material> { Arc::new(Metal::new(Vec3::new(0.8, 0.8, 0.8), 0.0)) };
+        check_bsdf(Arc::new(Mix::new(rust(), steel(), constant(0.3))));
+        let glossy: Arc<dyn Material> = Arc::new(Conductor::gold(constant(0.4)));
+        check_bsdf(Arc::new(Mix::new(rust(), glossy, constant(0.6))));
+
+        // Weights of zero and one give either material alone
+        let hit = hit_with(rust());
+        let (wo, wi) = (Vec3::new(0.6, 0.0, 0.8), Vec3::new(0.0, 0.6, 0.8));
+        let none = Mix::new(rust(), steel(), constant(0.0));
+        assert!((none.eval(&hit, &wo, &wi) - rust().eval(&hit, &wo, &wi)).mag() < 1e-6);
+        assert_eq!(none.flags(&hit), rust().flags(&hit));
+        let all = Mix::new(rust(), steel(), constant(1.0));
+        assert_eq!(all.flags(&hit), steel().flags(&hit));
+        assert_eq!(all.pdf(&hit, &wo, &wi), 0.0);
+    }
+
+    // Height rising along u
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, u: Float, _v: Float, _p: &Vec3) -> Vec3 {
+            Vec3::new(u, u, u)
+        }
+    }
+
+    #[test]
+    fn normal_and_bump_maps() {
+        let base = || -> Arc<dyn Material> { Arc::new(Lambertian::new(constant(0.5))) };
+        let mut hit = hit_with(base());
+        hit.dpdu = Vec3::new(2.0, 0.0, 0.0);
+        hit.dpdv = Vec3::new(0.0, 2.0, 0.0);
+        let shaded = |material: &dyn Material| {
+            let mut hit = hit_with(base());
+            hit.dpdu = Vec3::new(2.0, 0.0, 0.0);
+            hit.dpdv = Vec3::new(0.0, 2.0, 0.0);
+            material.apply_shading(&mut hit);
+            hit.shading_normal()
+        };
+
+        // A flat map and a constant height leave the normal alone
+        let flat_map = Arc::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 1.0)));
+        let flat = NormalMap::new(base(), flat_map);
+        assert!((shaded(&flat) - hit.normal).mag() < 1e-6);
+        let level = BumpMap::new(base(), constant(0.3), 1.0);
+        assert!((shaded(&level) - hit.normal).mag() < 1e-6);
+
+        // Tilts go toward dpdu for the map and away from the rising height
+        // for the bump
+        let tilted_map = Arc::new(ConstantTexture::new(Vec3::new(1.0, 0.5, 1.0)));
+        let tilted = NormalMap::new(base(), tilted_map);
+        let n = shaded(&tilted);
+        assert!(
+            (n - Vec3::new(1.0, 0.0, 1.0).unit()).mag() < 1e-5,
+            "{:?}",
+            n
+        );
+        let slope = BumpMap::new(base(), Arc::new(Ramp), 2.0);
+        let n = shaded(&slope);
+        assert!(
+            (n - Vec3::new(-1.0, 0.0, 1.0).unit()).mag() < 1e-3,
+            "{:?}",
+            n
+        );
+
+        // Directions the two normals put on different sides are rejected
+        hit.set_shading_normal(Vec3::new(1.0, 0.0, 1.0));
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        assert!(hit.sides_agree(&wo, &Vec3::new(0.0, 0.6, 0.8)));
+        assert!(!hit.sides_agree(&wo, &Vec3::new(0.9, 0.0, -0.1).unit()));
+        assert!(!hit.sides_agree(&wo, &Vec3::new(-0.9, 0.0, 0.1).unit()));
+    }
+
+    #[test]
+    fn anisotropic_conductor() {
+        let brushed = || Conductor::aluminium(constant(0.15)).with_roughness_v(constant(0.6));
+        check_bsdf(Arc::new(brushed().with_tangent_rotation(30.0)));
+        let flow = Arc::new(ConstantTexture::new(Vec3::new(0.5, 1.0, 0.5)));
+
+        // Highlights stretch across the tangent, where roughness is higher,
+        // and turning the tangent a quarter turn swaps the axes
+        let hit = hit_with(Arc::new(brushed()));
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        let along = Vec3::new(0.3, 0.0, 1.0).unit();
+        let across = Vec3::new(0.0, 0.3, 1.0).unit();
+        let m = brushed();
+        assert!(m.eval(&hit, &wo, &across).x() > 2.0 * m.eval(&hit, &wo, &along).x());
+        let turned = [
+            brushed().with_tangent_rotation(90.0),
+            brushed().with_tangent_map(flow),
+        ];
+        for turned in turned.iter() {
+            let (a, b) = (turned.eval(&hit, &wo, &along), m.eval(&hit, &wo, &across));
+            assert!((a - b).mag() < 1e-3 * b.mag(), "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn sheen() {
+        let cotton = || -> Arc<dyn Material> { Arc::new(Lambertian::new(constant(0.95))) };
+        check_bsdf(Arc::new(
+            Sheen::new(constant(1.0), constant(0.5)).with_base(cotton()),
+        ));
+
+        // The albedo table matches direct integration, on a finer grid of
+        // the hemisphere, between its nodes
+        let points: [(Float, Float); 3] = [(0.3, 0.5), (0.9, 0.25), (0.15, 0.9)];
+        for &(cos_o, roughness) in points.iter() {
+            let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+            let alpha = sheen_alpha(roughness);
+            let m = 150;
+            let mut sum = 0.0;
+            for a in 0..m {
+                for b in 0..m {
+                    let z = (a as Float + 0.5) / m as Float;
+                    let phi = 2.0 * consts::PI * (b as Float + 0.5) / m as Float;
+                    let r = (1.0 - z * z).sqrt();
+                    let wi = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                    sum += charlie_sheen(alpha, &wo, &wi) * z;
+                }
+            }
+            let direct = sum * 2.0 * consts::PI / (m * m) as Float;
+            let table = sheen_albedo(cos_o, roughness);
+            assert!((direct - table).abs() < 0.02, "{} {}", direct, table);
+        }
+
+        // Fibers light up seen and lit at grazing angles
+        let velvet = Sheen::new(constant(1.0), constant(0.5));
+        let hit = hit_with(cotton());
+        let grazing = Vec3::new(0.95, 0.0, 0.312).unit();
+        let (normal, oblique) = (Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.6, 0.0, 0.8));
+        let head_on = velvet.eval(&hit, &normal, &oblique).x();
+        assert!(velvet.eval(&hit, &grazing, &grazing).x() > 4.0 * head_on);
+    }
+
+    #[test]
+    fn diffuse_light() {
+        let mut hit = hit_with(Arc::new(DiffuseLight::new(constant(1.0))));
+        let p = Vec3::default();
+        let (up, down) = (Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let emitted = |light: &DiffuseLight, hit: &HitRecord, d: Vec3| {
+            light
+                .emitted(&Ray::new(p - d, d, 0.0), hit, 0.5, 0.5, &p)
+                .x()
+        };
+        let one_sided = DiffuseLight::new(constant(2.0));
+        assert_eq!(emitted(&one_sided, &hit, down), 2.0);
+        assert_eq!(emitted(&one_sided, &hit, up), 0.0);
+        let two_sided = DiffuseLight::new(constant(2.0))
+            .with_two_sided(true)
+            .with_scale(3.0);
+        assert_eq!(emitted(&two_sided, &hit, up), 6.0);
+
+        // Radiance integrated over the hemisphere, sides and area gives the
+        // power back, whichever of sidedness and power is set first
+        let bulbs = [
+            DiffuseLight::blackbody(2700.0)
+                .with_two_sided(true)
+                .with_power(60.0, 0.5),
+            DiffuseLight::blackbody(2700.0)
+                .with_power(60.0, 0.5)
+                .with_two_sided(true),
+        ];
+        for bulb in bulbs.iter() {
+            let r = Ray::new(p - up, up, 0.0);
+            let radiance = luminance(bulb.emitted(&r, &hit, 0.5, 0.5, &p));
+            assert!((radiance * consts::PI * 0.5 * 2.0 - 60.0).abs() < 1e-3);
+        }
+        let lamp = DiffuseLight::new(constant(1.0)).with_lumens(683.0, 1.0);
+        hit.normal = up;
+        assert!((emitted(&lamp, &hit, down) - 1.0 / consts::PI).abs() < 1e-6);
+    }
+
+    #[test]
+    fn thin_dielectric() {
+        check_bsdf(Arc::new(ThinDielectric::new(1.5)));
+        check_bsdf(Arc::new(ThinDielectric::new(1.33)));
+
+        // Both faces of a pane at normal incidence reflect about twice one
+        // face, and what is not reflected passes straight through
+        let pane = ThinDielectric::new(1.5);
+        assert!((pane.reflectance(1.0) - 0.0769).abs() < 1e-4);
+        let hit = hit_with(Arc::new(ThinDielectric::new(1.5)));
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        for &side in [1.0, -1.0].iter() {
+            let wo = wo * side;
+            let bs = pane.sample(&hit, &wo, (0.99, 0.5)).unwrap();
+            assert!((bs.wi + wo).mag() < 1e-6);
+            let bs = pane.sample(&hit, &wo, (0.0, 0.5)).unwrap();
+            assert!(bs.flags.contains(BsdfFlags::REFLECTION) && bs.wi.z() * wo.z() > 0.0);
+        }
+    }
+
+    #[test]
+    fn rough_dielectric() {
+        check_bsdf(Arc::new(RoughDielectric::new(1.5, constant(0.5))));
+
+        // Smooth glass reflects by its Fresnel reflectance and refracts the
+        // rest, each at unit weight, and nearly smooth glass has to approach
+        // it on average. Samples depend on u alone.
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let (refracted, _) = refract_about(&wo, &Vec3::new(0.0, 0.0, 1.0), 1.5).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        for roughness in [0.0, 0.05].iter() {
+            let glass: Arc<dyn Material> =
+                Arc::new(RoughDielectric::new(1.5, constant(*roughness)));
+            let hit = hit_with(glass.clone());
+            let n = 2000;
+            let mut reflected = 0;
+            let (mut mean_wi, mut mean_weight) = (Vec3::default(), Vec3::default());
+            for _ in 0..n {
+                let u = (rng.gen(), rng.gen());
+                let bs = glass.sample(&hit, &wo, u).unwrap();
+                assert!((glass.sample(&hit, &wo, u).unwrap().wi - bs.wi).mag() == 0.0);
+                if bs.wi.z() > 0.0 {
+                    reflected += 1;
+                } else {
+                    mean_wi += bs.wi / n as Float;
+                }
+                mean_weight += bs.f * bs.wi.z().abs() / bs.pdf / n as Float;
+            }
+            let r = fr_dielectric(0.8, 1.5);
+            assert!((reflected as Float / n as Float - r).abs() < 0.02);
+            let mean_wi = mean_wi / (1.0 - reflected as Float / n as Float);
+            assert!(
+                (mean_wi - refracted).mag() < 0.01,
+                "{:?} != {:?}",
+                mean_wi,
+                refracted
+            );
+            assert!(
+                (mean_weight - Vec3::new(1.0, 1.0, 1.0)).mag() < 0.02,
+                "{:?}",
+                mean_weight
+            );
+        }
+    }
+
+    #[test]
+    fn dielectric_fresnel() {
+        // Normal incidence ((n-1)/(n+1))^2, total internal reflection past
+        // the critical angle from inside
+        assert!((fr_dielectric(1.0, 1.5) - 0.04).abs() < 1e-5);
+        assert!((fr_dielectric(-1.0, 1.5) - 0.04).abs() < 1e-5);
+        assert_eq!(fr_dielectric(-0.5, 1.5), 1.0);
+        assert!((fr_dielectric(0.0, 1.5) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn principled() {
+        check_bsdf(Arc::new(
+            Principled::new(constant(0.4))
+                .with_sheen(constant(1.0))
+                .with_clearcoat(constant(1.0))
+                .with_transmission(constant(0.5)),
+        ));
+
+        // Head on, the diffuse lobe is Lambertian and the metallic one is
+        // the base color times D = 1 / (pi alpha^2)
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let plastic = Principled::new(constant(0.5)).with_specular(constant(0.0));
+        let hit = hit_with(Arc::new(Lambertian::new(constant(0.5))));
+        let f = plastic.eval(&hit, &up, &up).x();
+        assert!((f - 0.5 / consts::PI).abs() < 1e-5, "{}", f);
+        let metal = Principled::new(constant(0.5)).with_metallic(constant(1.0));
+        let alpha = TrowbridgeReitz::roughness_to_alpha(0.5);
+        let f = metal.eval(&hit, &up, &up).x();
+        let expected = 0.5 / (4.0 * consts::PI * alpha * alpha);
+        assert!(
+            (f - expected).abs() < 1e-3 * expected,
+            "{} != {}",
+            f,
+            expected
+        );
+    }
+
+    #[test]
+    fn coated() {
+        let white: Arc<dyn Material> = Arc::new(Lambertian::new(constant(1.0)));
+        check_bsdf(Arc::new(
+            Coated::new(Arc::new(Conductor::gold(constant(0.3))), 1.5)
+                .with_roughness(constant(0.4))
+                .with_absorption(Vec3::new(0.5, 1.0, 2.0), 0.2),
+        ));
+
+        // A white base under a clear coat loses nothing, whatever the angle
+        let material: Arc<dyn Material> = Arc::new(Coated::new(white, 1.5));
+        let hit = hit_with(material.clone());
+        let mut rng = StdRng::seed_from_u64(1);
+        let n = 4000;
+        for &cos_o in [0.9, 0.3].iter() {
+            let wo = Vec3::new((1.0 - cos_o * cos_o as Float).sqrt(), 0.0, cos_o);
+            let mut albedo = 0.0;
+            for _ in 0..n {
+                if let Some(bs) = material.sample(&hit, &wo, (rng.gen(), rng.gen())) {
+                    albedo += bs.f.x() * bs.wi.z().abs() / bs.pdf / n as Float;
+                }
+            }
+            assert!(
+                (albedo - 1.0).abs() < 0.05,
+                "albedo {} at cos {}",
+                albedo,
+                cos_o
+            );
+        }
+    }
+
+    #[test]
+    fn isotropic() {
+        check_bsdf(Arc::new(Isotropic::new(constant(0.9))));
     }
 }
@@ -0,0 +1,335 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::hit::HitRecord;
+use crate::material::{same_hemisphere, upper, BsdfFlags, BsdfSample, Material};
+use crate::microfacet::reflect_about;
+use crate::util::*;
+use crate::vec3::*;
+
+// Factors the stored values are multiplied by to give RGB reflectance
+const SCALE: [Float; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+// Measured isotropic BRDF in the MERL format (Matusik et al. 2003), a table
+// over the half angle theta_h and the difference angles theta_d and phi_d
+// (Rusinkiewicz 1998), 90x90x180 in the MERL database. Sampling mixes cosine
+// sampling with half vectors drawn from the table's reflectance per theta_h.
+// Two-sided.
+pub struct Merl {
+    // Resolution in theta_h, theta_d and phi_d
+    res: [usize; 3],
+    // Per channel, in table order
+    values: Vec<Vec3>,
+    // Probability of each theta_h bin, and their running sums
+    theta_h_pmf: Vec<Float>,
+    theta_h_cdf: Vec<Float>,
+}
+
+// Rotates w about axis (unit x, y or z) by angle
+fn rotate(w: Vec3, axis: usize, angle: Float) -> Vec3 {
+    let (s, c) = angle.sin_cos();
+    match axis {
+        1 => Vec3::new(c * w.x() + s * w.z(), w.y(), -s * w.x() + c * w.z()),
+        _ => Vec3::new(c * w.x() - s * w.y(), s * w.x() + c * w.y(), w.z()),
+    }
+}
+
+// (1 - cos(theta)) / 2, which unlike the cosine keeps its precision for the
+// tiny angles of the first bins
+#[inline]
+fn haversine(theta: Float) -> Float {
+    let s = (0.5 * theta).sin();
+    s * s
+}
+
+// Index of x in [0, range) split into n equal bins
+#[inline]
+fn bin(x: Float, range: Float, n: usize) -> usize {
+    ((x / range * n as Float).max(0.0) as usize).min(n - 1)
+}
+
+impl Merl {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    // Three little endian i32 dimensions, then the red, green and blue
+    // tables of f64
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < 12 {
+            return Err(invalid("MERL file too short for its header"));
+        }
+        let mut dims = [0i32; 3];
+        for (i, d) in dims.iter_mut().enumerate() {
+            let mut b = [0u8; 4];
+            b.copy_from_slice(&bytes[4 * i..4 * i + 4]);
+            *d = i32::from_le_bytes(b);
+        }
+        if dims.iter().any(|&d| d <= 0) {
+            return Err(invalid("MERL table has an empty dimension"));
+        }
+        let res = [dims[0] as usize, dims[1] as usize, dims[2] as usize];
+        let size = res.iter().try_fold(1usize, |n, &d| n.checked_mul(d));
+        let data = &bytes[12..];
+        let size = match size {
+            Some(size) if size.checked_mul(24) == Some(data.len()) => size,
+            _ => return Err(invalid("MERL table size does not match its dimensions")),
+        };
+
+        let value = |i: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&data[8 * i..8 * i + 8]);
+            // Negative entries mark angles that were not measured
+            (f64::from_le_bytes(b) as Float).max(0.0)
+        };
+        let values: Vec<Vec3> = (0..size)
+            .map(|i| {
+                Vec3::new(
+                    value(i) * SCALE[0],
+                    value(size + i) * SCALE[1],
+                    value(2 * size + i) * SCALE[2],
+                )
+            })
+            .collect();
+        let mut merl = Self {
+            res,
+            values,
+            theta_h_pmf: Vec::with_capacity(res[0]),
+            theta_h_cdf: Vec::with_capacity(res[0] + 1),
+        };
+
+        // Each theta_h bin's share of reflectance, from its mean value and
+        // the solid angle of half vectors it covers
+        let per_bin = res[1] * res[2];
+        for i in 0..res[0] {
+            let sum: Float = merl.values[i * per_bin..(i + 1) * per_bin]
+                .iter()
+                .map(|v| (v.x() + v.y() + v.z()) / 3.0)
+                .sum();
+            let p = sum / per_bin as Float * merl.theta_h_solid_angle(i);
+            merl.theta_h_pmf.push(p);
+        }
+        let total: Float = merl.theta_h_pmf.iter().sum();
+        if total <= 0.0 {
+            return Err(invalid("MERL table has no reflectance"));
+        }
+        merl.theta_h_cdf.push(0.0);
+        for i in 0..res[0] {
+            merl.theta_h_pmf[i] /= total;
+            let last = merl.theta_h_cdf[i];
+            merl.theta_h_cdf.push(last + merl.theta_h_pmf[i]);
+        }
+        Ok(merl)
+    }
+
+    // Edges of theta_h bin i, which the table spaces quadratically to
+    // resolve highlights
+    fn theta_h_edges(&self, i: usize) -> (Float, Float) {
+        let edge = |i: usize| {
+            let t = i as Float / self.res[0] as Float;
+            t * t * consts::FRAC_PI_2
+        };
+        (edge(i), edge(i + 1))
+    }
+
+    // Solid angle of half vectors in theta_h bin i
+    fn theta_h_solid_angle(&self, i: usize) -> Float {
+        let (lo, hi) = self.theta_h_edges(i);
+        4.0 * consts::PI * (haversine(hi) - haversine(lo))
+    }
+
+    fn theta_h_index(&self, theta_h: Float) -> usize {
+        bin(
+            theta_h.max(0.0).sqrt(),
+            consts::FRAC_PI_2.sqrt(),
+            self.res[0],
+        )
+    }
+
+    // Table lookup for wo and wi above the surface
+    fn lookup(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let wh = (*wo + *wi).unit();
+        let theta_h = wh.z().clamp(-1.0, 1.0).acos();
+        let phi_h = wh.y().atan2(wh.x());
+        // wi in the frame where the half vector is the normal
+        let diff = rotate(rotate(*wi, 2, -phi_h), 1, -theta_h);
+        let theta_d = diff.z().clamp(-1.0, 1.0).acos();
+        let phi_d = diff.y().atan2(diff.x());
+        // Reciprocity folds phi_d into [0, pi)
+        let phi_d = if phi_d < 0.0 {
+            phi_d + consts::PI
+        } else {
+            phi_d
+        };
+        let [_, n_theta_d, n_phi_d] = self.res;
+        let index = bin(phi_d, consts::PI, n_phi_d)
+            + n_phi_d
+                * (bin(theta_d, consts::FRAC_PI_2, n_theta_d)
+                    + n_theta_d * self.theta_h_index(theta_h));
+        self.values[index]
+    }
+
+    // Density of half vector wh, per solid angle
+    fn half_vector_pdf(&self, wh: &Vec3) -> Float {
+        let theta_h = wh.z().clamp(-1.0, 1.0).acos();
+        let i = self.theta_h_index(theta_h);
+        self.theta_h_pmf[i] / self.theta_h_solid_angle(i)
+    }
+
+    // Half vector whose theta_h is drawn from the table and spread
+    // uniformly in solid angle within its bin
+    fn sample_half_vector(&self, u: (Float, Float)) -> Vec3 {
+        let i = match self.theta_h_cdf.iter().position(|&c| c > u.0) {
+            Some(i) => i.max(1) - 1,
+            None => self.res[0] - 1,
+        };
+        let (lo, hi) = self.theta_h_edges(i);
+        let pmf = self.theta_h_pmf[i];
+        let t = if pmf > 0.0 {
+            ((u.0 - self.theta_h_cdf[i]) / pmf).clamp(0.0, 1.0)
+        } else {
+            0.5
+        };
+        let v = haversine(lo) + t * (haversine(hi) - haversine(lo));
+        let cos_theta = 1.0 - 2.0 * v;
+        let sin_theta = 2.0 * (v * (1.0 - v)).max(0.0).sqrt();
+        let phi = 2.0 * consts::PI * u.1;
+        Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+    }
+}
+
+impl Material for Merl {
+    fn kind(&self) -> &'static str {
+        "merl"
+    }
+    fn eval(&self, _hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if !same_hemisphere(wo, wi) {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let flip = wo.z() < 0.0;
+        self.lookup(&upper(wo, flip), &upper(wi, flip))
+    }
+    fn sample(&self, hit: &HitRecord, wo: &Vec3, u: (Float, Float)) -> Option<BsdfSample> {
+        if wo.z() == 0.0 {
+            return None;
+        }
+        let flip = wo.z() < 0.0;
+        let wo_up = upper(wo, flip);
+        let wi = if u.0 < 0.5 {
+            cosine_sample_hemisphere(((2.0 * u.0).min(1.0 - Float::EPSILON), u.1))
+        } else {
+            let u0 = (2.0 * u.0 - 1.0).min(1.0 - Float::EPSILON);
+            reflect_about(&wo_up, &self.sample_half_vector((u0, u.1)))
+        };
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        let wi = upper(&wi, flip);
+        let pdf = self.pdf(hit, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let flags = BsdfFlags::GLOSSY | BsdfFlags::REFLECTION;
+        Some(BsdfSample::new(wi, self.eval(hit, wo, &wi), pdf, flags))
+    }
+    fn pdf(&self, _hit: &HitRecord, wo: &Vec3, wi: &Vec3) -> Float {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let flip = wo.z() < 0.0;
+        let (wo, wi) = (upper(wo, flip), upper(wi, flip));
+        let wh = (wo + wi).unit();
+        let cosine = wi.z() / consts::PI;
+        let tabulated = self.half_vector_pdf(&wh) / (4.0 * dot(wo, wh).abs());
+        0.5 * cosine + 0.5 * tabulated
+    }
+    fn flags(&self, _hit: &HitRecord) -> BsdfFlags {
+        BsdfFlags::GLOSSY | BsdfFlags::REFLECTION
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::testing::{check_bsdf, hit_with};
+    use std::sync::Arc;
+
+    // Small resolution in theta_h, theta_d and phi_d for synthetic tables
+    const RES: [usize; 3] = [12, 6, 12];
+
+    // A MERL file whose stored value at each (theta_h, theta_d, phi_d)
+    // index is f(theta_h index) for every channel
+    fn merl_bytes<F: Fn(usize) -> f64>(f: F) -> Vec<u8> {
+        let size = RES[0] * RES[1] * RES[2];
+        let mut bytes = Vec::with_capacity(12 + 3 * size * 8);
+        for &d in RES.iter() {
+            bytes.extend_from_slice(&(d as i32).to_le_bytes());
+        }
+        for &scale in SCALE.iter() {
+            for i in 0..size {
+                let v = f(i / (RES[1] * RES[2])) / scale as f64;
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn constant_table_is_lambertian() {
+        let albedo = 0.8;
+        let matte = Arc::new(Merl::parse(&merl_bytes(|_| albedo / std::f64::consts::PI)).unwrap());
+        check_bsdf(matte.clone());
+        let hit = hit_with(matte.clone());
+        let (wo, wi) = (Vec3::new(0.6, 0.0, 0.8), Vec3::new(-0.3, 0.5, 0.81).unit());
+        let f = matte.eval(&hit, &wo, &wi);
+        assert!((f - Vec3::new(1.0, 1.0, 1.0) * (albedo as Float / consts::PI)).mag() < 1e-4);
+        // Seen from below too
+        let f = matte.eval(&hit, &-wo, &-wi);
+        assert!((f.x() - albedo as Float / consts::PI).abs() < 1e-4);
+    }
+
+    #[test]
+    fn highlight_is_sampled_through_table() {
+        let highlight = |i: usize| 0.05 + 3.0 * (-(i as f64) / 1.5).exp();
+        let glossy = Merl::parse(&merl_bytes(highlight)).unwrap();
+        let near = glossy.half_vector_pdf(&Vec3::new(0.01, 0.0, 1.0).unit());
+        let far = glossy.half_vector_pdf(&Vec3::new(1.0, 0.0, 1.0).unit());
+        assert!(near > 10.0 * far, "{} {}", near, far);
+
+        // Half vector probabilities cover the sphere of half vectors once
+        let total: Float = glossy.theta_h_pmf.iter().sum();
+        assert!((total - 1.0).abs() < 1e-4);
+
+        // Samples agree with eval and pdf, with the highlight kept low enough
+        // that the table reflects less than it receives
+        check_bsdf(Arc::new(glossy));
+    }
+
+    #[test]
+    fn bad_files_are_reported() {
+        let bytes = merl_bytes(|_| 0.1);
+        let truncated = Merl::parse(&bytes[..bytes.len() - 8]);
+        assert_eq!(truncated.err().unwrap().kind(), io::ErrorKind::InvalidData);
+        let mut resized = bytes.clone();
+        resized[0] = 45;
+        assert_eq!(
+            Merl::parse(&resized).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let mut empty = bytes.clone();
+        empty[4..8].copy_from_slice(&0i32.to_le_bytes());
+        assert_eq!(
+            Merl::parse(&empty).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let black = merl_bytes(|_| 0.0);
+        assert_eq!(
+            Merl::parse(&black).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(Merl::parse(&bytes[..8]).is_err());
+        assert!(Merl::load("no/such/file.binary").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::vec3::*;
+
+// Trigonometry of directions in the local shading frame
+#[inline]
+pub fn cos2_theta(w: &Vec3) -> Float {
+    w.z() * w.z()
+}
+
+#[inline]
+pub fn sin2_theta(w: &Vec3) -> Float {
+    (1.0 - cos2_theta(w)).max(0.0)
+}
+
+#[inline]
+pub fn tan2_theta(w: &Vec3) -> Float {
+    sin2_theta(w) / cos2_theta(w)
+}
+
+#[inline]
+pub fn cos_phi(w: &Vec3) -> Float {
+    let sin_theta = sin2_theta(w).sqrt();
+    if sin_theta == 0.0 {
+        1.0
+    } else {
+        (w.x() / sin_theta).clamp(-1.0, 1.0)
+    }
+}
+
+#[inline]
+pub fn sin_phi(w: &Vec3) -> Float {
+    let sin_theta = sin2_theta(w).sqrt();
+    if sin_theta == 0.0 {
+        0.0
+    } else {
+        (w.y() / sin_theta).clamp(-1.0, 1.0)
+    }
+}
+
+// Mirror of wo about the microfacet normal wm
+#[inline]
+pub fn reflect_about(wo: &Vec3, wm: &Vec3) -> Vec3 {
+    -*wo + 2.0 * dot(*wo, *wm) * *wm
+}
+
+// Refracts wo through a facet with normal wm for relative index of
+// refraction eta, returning the direction and the eta it saw. None on total
+// internal reflection.
+pub fn refract_about(wo: &Vec3, wm: &Vec3, eta: Float) -> Option<(Vec3, Float)> {
+    let mut cos_o = dot(*wo, *wm);
+    let (mut n, mut eta) = (*wm, eta);
+    if cos_o < 0.0 {
+        eta = 1.0 / eta;
+        cos_o = -cos_o;
+        n = -n;
+    }
+    let sin2_o = (1.0 - cos_o * cos_o).max(0.0);
+    let sin2_t = sin2_o / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some((-*wo / eta + (cos_o / eta - cos_t) * n, eta))
+}
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals with Smith
+// masking, sampled through its distribution of visible normals (Heitz 2018)
+#[derive(Copy, Clone)]
+pub struct TrowbridgeReitz {
+    alpha_x: Float,
+    alpha_y: Float,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: Float, alpha_y: Float) -> Self {
+        Self {
+            alpha_x: alpha_x.max(1e-4),
+            alpha_y: alpha_y.max(1e-4),
+        }
+    }
+    // Perceptually linear roughness in [0, 1] to alpha
+    pub fn roughness_to_alpha(roughness: Float) -> Float {
+        roughness * roughness
+    }
+    // Below this the distribution is treated as a perfect mirror
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: &Vec3) -> Float {
+        let tan2 = tan2_theta(wm);
+        if !tan2.is_finite() {
+            return 0.0;
+        }
+        let cos4 = cos2_theta(wm) * cos2_theta(wm);
+        if cos4 < 1e-16 {
+            return 0.0;
+        }
+        let (cp, sp) = (cos_phi(wm), sin_phi(wm));
+        let e = tan2 * ((cp / self.alpha_x).powi(2) + (sp / self.alpha_y).powi(2));
+        1.0 / (consts::PI * self.alpha_x * self.alpha_y * cos4 * (1.0 + e) * (1.0 + e))
+    }
+    pub fn lambda(&self, w: &Vec3) -> Float {
+        let tan2 = tan2_theta(w);
+        if !tan2.is_finite() {
+            return 0.0;
+        }
+        let (cp, sp) = (cos_phi(w), sin_phi(w));
+        let alpha2 = (cp * self.alpha_x).powi(2) + (sp * self.alpha_y).powi(2);
+        ((1.0 + alpha2 * tan2).sqrt() - 1.0) / 2.0
+    }
+    pub fn g1(&self, w: &Vec3) -> Float {
+        1.0 / (1.0 + self.lambda(w))
+    }
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> Float {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+    // Density of normals visible from w
+    pub fn d_visible(&self, w: &Vec3, wm: &Vec3) -> Float {
+        let cos_theta = w.z().abs();
+        if cos_theta == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / cos_theta * self.d(wm) * dot(*w, *wm).abs()
+    }
+    // Samples a normal visible from w, which must lie above the surface
+    pub fn sample_wm(&self, w: &Vec3, u: (Float, Float)) -> Vec3 {
+        let wh = Vec3::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()).unit();
+        let wh = if wh.z() < 0.0 { -wh } else { wh };
+        let t1 = if wh.z() < 0.99999 {
+            cross(Vec3::new(0.0, 0.0, 1.0), wh).unit()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = cross(wh, t1);
+
+        // Uniform point on the disk, warped to the projected visible area
+        let r = u.0.sqrt();
+        let phi = 2.0 * consts::PI * u.1;
+        let (px, py) = (r * phi.cos(), r * phi.sin());
+        let h = (1.0 - px * px).sqrt();
+        let s = (1.0 + wh.z()) / 2.0;
+        let py = (1.0 - s) * h + s * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+
+        let nh = px * t1 + py * t2 + pz * wh;
+        Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        )
+        .unit()
+    }
+}
@@ -1,30 +1,32 @@
 use std::sync::Arc;
 
+use crate::bvh::AABB;
 use crate::hit::*;
 use crate::material::*;
 use crate::texture::*;
 use crate::transf::*;
-use crate::vec3::Vec3;
+use crate::util::hash_float;
+use crate::vec3::{consts, dot, Float, Ray, Vec3};
 
 pub struct Sphere {
     pub center: Vec3,
-    pub radius: f32,
+    pub radius: Float,
     pub material: Arc<dyn Material>,
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f32, material: Arc<dyn Material>) -> Sphere {
+    pub fn new(center: Vec3, radius: Float, material: Arc<dyn Material>) -> Sphere {
         Sphere {
             center: center,
             radius: radius,
             material: material,
         }
     }
-    pub fn get_sphere_uv(p: &Vec3) -> (f32, f32) {
+    pub fn get_sphere_uv(p: &Vec3) -> (Float, Float) {
         let phi = p.z().atan2(p.x());
         let theta = p.y().asin();
-        let u = 1.0 - (phi + std::f32::consts::PI) / (2.0 * std::f32::consts::PI);
-        let v = (theta + std::f32::consts::FRAC_PI_2) / std::f32::consts::PI;
+        let u = 1.0 - (phi + consts::PI) / (2.0 * consts::PI);
+        let v = (theta + consts::FRAC_PI_2) / consts::PI;
         (u, v)
     }
 }
@@ -32,9 +34,9 @@ impl Sphere {
 pub struct MovingSphere {
     pub center0: Vec3,
     pub center1: Vec3,
-    pub time0: f32,
-    pub time1: f32,
-    pub radius: f32,
+    pub time0: Float,
+    pub time1: Float,
+    pub radius: Float,
     pub material: Arc<dyn Material>,
 }
 
@@ -42,9 +44,9 @@ impl MovingSphere {
     pub fn new(
         center0: Vec3,
         center1: Vec3,
-        t0: f32,
-        t1: f32,
-        radius: f32,
+        t0: Float,
+        t1: Float,
+        radius: Float,
         material: Arc<dyn Material>,
     ) -> Self {
         Self {
@@ -56,23 +58,30 @@ impl MovingSphere {
             material: material,
         }
     }
-    pub fn center(&self, time: f32) -> Vec3 {
+    pub fn center(&self, time: Float) -> Vec3 {
         self.center0
             + ((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
     }
 }
 
 pub struct XYRect {
-    pub x0: f32,
-    pub x1: f32,
-    pub y0: f32,
-    pub y1: f32,
-    pub k: f32,
+    pub x0: Float,
+    pub x1: Float,
+    pub y0: Float,
+    pub y1: Float,
+    pub k: Float,
     pub material: Arc<dyn Material>,
 }
 
 impl XYRect {
-    pub fn new(x0: f32, x1: f32, y0: f32, y1: f32, k: f32, material: Arc<dyn Material>) -> Self {
+    pub fn new(
+        x0: Float,
+        x1: Float,
+        y0: Float,
+        y1: Float,
+        k: Float,
+        material: Arc<dyn Material>,
+    ) -> Self {
         Self {
             x0: x0,
             x1: x1,
@@ -85,16 +94,23 @@ impl XYRect {
 }
 
 pub struct XZRect {
-    pub x0: f32,
-    pub x1: f32,
-    pub z0: f32,
-    pub z1: f32,
-    pub k: f32,
+    pub x0: Float,
+    pub x1: Float,
+    pub z0: Float,
+    pub z1: Float,
+    pub k: Float,
     pub material: Arc<dyn Material>,
 }
 
 impl XZRect {
-    pub fn new(x0: f32, x1: f32, z0: f32, z1: f32, k: f32, material: Arc<dyn Material>) -> Self {
+    pub fn new(
+        x0: Float,
+        x1: Float,
+        z0: Float,
+        z1: Float,
+        k: Float,
+        material: Arc<dyn Material>,
+    ) -> Self {
         Self {
             x0: x0,
             x1: x1,
@@ -107,16 +123,23 @@ impl XZRect {
 }
 
 pub struct YZRect {
-    pub y0: f32,
-    pub y1: f32,
-    pub z0: f32,
-    pub z1: f32,
-    pub k: f32,
+    pub y0: Float,
+    pub y1: Float,
+    pub z0: Float,
+    pub z1: Float,
+    pub k: Float,
     pub material: Arc<dyn Material>,
 }
 
 impl YZRect {
-    pub fn new(y0: f32, y1: f32, z0: f32, z1: f32, k: f32, material: Arc<dyn Material>) -> Self {
+    pub fn new(
+        y0: Float,
+        y1: Float,
+        z0: Float,
+        z1: Float,
+        k: Float,
+        material: Arc<dyn Material>,
+    ) -> Self {
         Self {
             y0: y0,
             y1: y1,
@@ -195,14 +218,31 @@ impl BoxShape {
     }
 }
 
+// Gives an object a stable id, and optionally a name, reported in its hits
+pub struct Tagged {
+    pub obj_ref: Arc<dyn Hittable>,
+    pub id: usize,
+    pub name: Option<Arc<str>>,
+}
+
+impl Tagged {
+    pub fn new(obj_ref: Arc<dyn Hittable>, id: usize, name: Option<&str>) -> Self {
+        Self {
+            obj_ref,
+            id,
+            name: name.map(Arc::from),
+        }
+    }
+}
+
 pub struct ConstantMedium {
     pub boundary: Arc<dyn Hittable>,
-    pub density: f32,
+    pub density: Float,
     pub phase_function: Arc<dyn Material>,
 }
 
 impl ConstantMedium {
-    pub fn new(b: Arc<dyn Hittable>, d: f32, a: Arc<dyn Texture>) -> Self {
+    pub fn new(b: Arc<dyn Hittable>, d: Float, a: Arc<dyn Texture>) -> Self {
         Self {
             boundary: b,
             density: d,
@@ -210,3 +250,69 @@ impl ConstantMedium {
         }
     }
 }
+
+// Translucent object whose interior light wanders through by a volumetric
+// random walk, as in skin, wax, marble or milk. Like ConstantMedium it
+// samples scattering distances along rays inside a closed boundary, but each
+// step's distance is drawn per channel and light enters and leaves through
+// a smooth surface of index ref_idx. Walks of many steps need a deep path
+// depth.
+pub struct SubsurfaceMedium {
+    pub boundary: Arc<dyn Hittable>,
+    pub walk: RandomWalk,
+    pub surface: Arc<dyn Material>,
+    pub phase_function: Arc<dyn Material>,
+}
+
+impl SubsurfaceMedium {
+    // mean_free_path is per channel in scene units, albedo the single
+    // scattering albedo
+    pub fn new(b: Arc<dyn Hittable>, mean_free_path: Vec3, albedo: Vec3, ref_idx: Float) -> Self {
+        let walk = RandomWalk::new(mean_free_path, albedo);
+        Self {
+            boundary: b,
+            walk,
+            surface: Arc::new(Subsurface::new(walk, ref_idx)),
+            phase_function: Arc::new(SubsurfacePhase::new(walk)),
+        }
+    }
+}
+
+impl Hittable for SubsurfaceMedium {
+    fn hit(&self, r: Ray, t_min: Float, t_max: Float) -> Option<HitRecord> {
+        let mut hit = self.boundary.hit(r, t_min, Float::INFINITY)?;
+        // Rays leaving through the boundary started inside, and take a step
+        // of the walk first. Rays have unit directions, so t is the step's
+        // length. The step is hashed from the ray, so that every test of one
+        // ray sees the same step.
+        if dot(r.direction(), hit.normal) > 0.0 {
+            let u = (
+                hash_float(r.origin(), r.direction()),
+                hash_float(r.direction(), r.origin()),
+            );
+            let t = self.walk.sample_distance(u).max(t_min);
+            if t < hit.t {
+                if t > t_max {
+                    return None;
+                }
+                return Some(HitRecord::new(
+                    t,
+                    r.point_at_parameter(t),
+                    Vec3::new(0.0, 0.0, 0.0),
+                    Vec3::new(1.0, 0.0, 0.0),
+                    0.0,
+                    0.0,
+                    self.phase_function.clone(),
+                ));
+            }
+        }
+        if hit.t > t_max {
+            return None;
+        }
+        hit.material = self.surface.clone();
+        Some(hit)
+    }
+    fn bounding_box(&self, t0: Float, t1: Float) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+}
@@ -1,7 +1,7 @@
 use rand::seq::SliceRandom;
 
 use crate::util::*;
-use crate::vec3::{dot, Vec3};
+use crate::vec3::{dot, Float, Vec3};
 
 pub struct Perlin {
     ranvec: Vec<Vec3>,
@@ -20,7 +20,7 @@ impl Perlin {
         }
     }
 
-    pub fn noise(&self, p: &Vec3) -> f32 {
+    pub fn noise(&self, p: &Vec3) -> Float {
         let u = p.x() - p.x().floor();
         let v = p.y() - p.y().floor();
         let w = p.z() - p.z().floor();
@@ -61,7 +61,7 @@ impl Perlin {
         p
     }
 
-    fn interp(c: [[[Vec3; 2]; 2]; 2], u: f32, v: f32, w: f32) -> f32 {
+    fn interp(c: [[[Vec3; 2]; 2]; 2], u: Float, v: Float, w: Float) -> Float {
         let uu = u * u * (3.0 - 2.0 * u);
         let vv = v * v * (3.0 - 2.0 * v);
         let ww = w * w * (3.0 - 2.0 * w);
@@ -69,10 +69,10 @@ impl Perlin {
         for i in 0..2 {
             for j in 0..2 {
                 for k in 0..2 {
-                    let weight_v = Vec3::new(u - i as f32, v - j as f32, w - k as f32);
-                    accum += (i as f32 * uu + (1.0 - i as f32) * (1.0 - uu))
-                        * (j as f32 * vv + (1.0 - j as f32) * (1.0 - vv))
-                        * (k as f32 * ww + (1.0 - k as f32) * (1.0 - ww))
+                    let weight_v = Vec3::new(u - i as Float, v - j as Float, w - k as Float);
+                    accum += (i as Float * uu + (1.0 - i as Float) * (1.0 - uu))
+                        * (j as Float * vv + (1.0 - j as Float) * (1.0 - vv))
+                        * (k as Float * ww + (1.0 - k as Float) * (1.0 - ww))
                         * dot(c[i][j][k], weight_v);
                 }
             }
@@ -80,7 +80,7 @@ impl Perlin {
         accum
     }
 
-    pub fn turb(&self, p: &Vec3, depth: u32) -> f32 {
+    pub fn turb(&self, p: &Vec3, depth: u32) -> Float {
         let mut accum = 0.0;
         let mut weight = 1.0;
         let mut temp_p = Vec3::new(p.x(), p.y(), p.z());
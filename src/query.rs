@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use crate::camera::Camera;
+use crate::hit::*;
+use crate::material::Material;
+use crate::util::offset_ray_origin;
+use crate::vec3::*;
+
+// What a ray found first in the scene
+pub struct QueryHit {
+    pub object_id: Option<usize>,
+    pub name: Option<Arc<str>>,
+    pub material: Arc<dyn Material>,
+    pub t: Float,
+    pub point: Vec3,
+    // Bound on the error in point, for offsetting rays leaving it
+    p_error: Vec3,
+    pub normal: Vec3,
+    pub u: Float,
+    pub v: Float,
+}
+
+impl QueryHit {
+    fn from_hit(hit: HitRecord) -> Self {
+        Self {
+            object_id: hit.object_id,
+            name: hit.name,
+            material: hit.material,
+            t: hit.t,
+            point: hit.p,
+            p_error: hit.p_error,
+            normal: hit.normal,
+            u: hit.u,
+            v: hit.v,
+        }
+    }
+
+    // Point just off the surface on the side w points to, for rays leaving
+    // it, moved by the error bound on its position as spawned rays are
+    pub fn origin_toward(&self, w: Vec3) -> Vec3 {
+        offset_ray_origin(self.point, self.p_error, self.normal, w)
+    }
+}
+
+// Deterministic ray casts against a scene, for picking, line of sight and
+// measuring distances without rendering
+pub struct SceneQuery<'a> {
+    camera: &'a Camera,
+    world: &'a dyn Hittable,
+}
+
+impl<'a> SceneQuery<'a> {
+    pub fn new(camera: &'a Camera, world: &'a dyn Hittable) -> Self {
+        Self { camera, world }
+    }
+
+    pub fn cast(&self, r: Ray) -> Option<QueryHit> {
+        self.world.hit(r, 0.0, Float::MAX).map(QueryHit::from_hit)
+    }
+
+    // Casts through the center of pixel (x, y) of an nx by ny image, with
+    // rows counted from the top as they are written out
+    pub fn pick(&self, x: u32, y: u32, nx: u32, ny: u32) -> Option<QueryHit> {
+        let s = (x as Float + 0.5) / nx as Float;
+        let t = (ny as Float - y as Float - 0.5) / ny as Float;
+        self.cast(self.camera.get_pinhole_ray(s, t))
+    }
+
+    // Whether nothing lies between two surface points, such as two picks.
+    // Both ends are moved off their surfaces by the error bounds on their
+    // positions, as spawned rays are, so neither surface blocks the view.
+    pub fn line_of_sight(&self, from: &QueryHit, to: &QueryHit) -> bool {
+        let origin = from.origin_toward(to.point - from.point);
+        let target = to.origin_toward(origin - to.point);
+        let r = Ray::new(origin, target - origin, self.camera_time());
+        self.world.hit(r, 0.0, 1.0).is_none()
+    }
+
+    // Distance to the first surface along a direction
+    pub fn distance(&self, from: Vec3, direction: Vec3) -> Option<Float> {
+        let r = Ray::new(from, direction.unit(), self.camera_time());
+        self.cast(r).map(|hit| hit.t)
+    }
+
+    fn camera_time(&self) -> Float {
+        self.camera.get_pinhole_ray(0.5, 0.5).time()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::obj::{Sphere, Tagged};
+    use crate::texture::ConstantTexture;
+
+    fn scene() -> (Camera, Vec<Arc<dyn Hittable>>) {
+        let gray = Arc::new(Lambertian::new(Arc::new(ConstantTexture::new(Vec3::new(
+            0.5, 0.5, 0.5,
+        )))));
+        let world: Vec<Arc<dyn Hittable>> = vec![
+            Arc::new(Tagged::new(
+                Arc::new(Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, gray.clone())),
+                7,
+                Some("ball"),
+            )),
+            Arc::new(Sphere::new(Vec3::new(0.0, -1001.0, -5.0), 1000.0, gray)),
+        ];
+        let cam = Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            0.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        (cam, world)
+    }
+
+    #[test]
+    fn pick_reports_object() {
+        let (cam, world) = scene();
+        let query = SceneQuery::new(&cam, &world);
+
+        let hit = query.pick(50, 50, 101, 101).expect("center pixel missed");
+        assert_eq!(hit.object_id, Some(7));
+        assert_eq!(hit.name.as_deref(), Some("ball"));
+        assert!((hit.t - 4.0).abs() < 1e-3);
+        assert!((hit.normal.z() - 1.0).abs() < 1e-3);
+        assert_eq!(hit.material.kind(), "lambertian");
+
+        let floor = query.pick(50, 100, 101, 101).expect("bottom pixel missed");
+        assert_eq!(floor.object_id, None);
+        assert!(query.pick(50, 0, 101, 101).is_none());
+    }
+
+    #[test]
+    fn line_of_sight_and_distance() {
+        let (cam, world) = scene();
+        let query = SceneQuery::new(&cam, &world);
+
+        // The front of the ball sees the floor in front of it but not the
+        // floor behind it, and neither end blocks the view by itself
+        let ball = query.pick(50, 50, 101, 101).unwrap();
+        let front = query.pick(50, 100, 101, 101).unwrap();
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let behind = query
+            .cast(Ray::new(Vec3::new(0.0, 0.5, -7.0), down, 0.0))
+            .unwrap();
+        assert_eq!(behind.object_id, None);
+        assert!(query.line_of_sight(&ball, &front));
+        assert!(query.line_of_sight(&front, &ball));
+        assert!(!query.line_of_sight(&ball, &behind));
+
+        let d = query
+            .distance(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0))
+            .unwrap();
+        assert!((d - 4.0).abs() < 1e-3);
+
+        // Into the ball from its front, the back is a diameter away
+        let into = Vec3::new(0.0, 0.0, -1.0);
+        let d = query.distance(ball.origin_toward(into), into).unwrap();
+        assert!((d - 2.0).abs() < 1e-3, "{}", d);
+    }
+}
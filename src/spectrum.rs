@@ -0,0 +1,151 @@
+use std::sync::OnceLock;
+
+use crate::vec3::*;
+
+// Visible range spectral quantities are integrated over, in nm
+pub const LAMBDA_MIN: Float = 380.0;
+pub const LAMBDA_MAX: Float = 780.0;
+
+// Piecewise Gaussian with different widths either side of its peak
+#[inline]
+fn g(x: Float, mu: Float, s1: Float, s2: Float) -> Float {
+    let t = (x - mu) / if x < mu { s1 } else { s2 };
+    (-0.5 * t * t).exp()
+}
+
+// CIE 1931 2 degree color matching functions at wavelength lambda in nm,
+// from the multi-lobe fits of Wyman, Sloan and Shirley 2013
+pub fn cie_xyz(lambda: Float) -> Vec3 {
+    let x = 1.056 * g(lambda, 599.8, 37.9, 31.0) + 0.362 * g(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * g(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * g(lambda, 568.8, 46.9, 40.5) + 0.286 * g(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * g(lambda, 437.0, 11.8, 36.0) + 0.681 * g(lambda, 459.0, 26.0, 13.8);
+    Vec3::new(x, y, z)
+}
+
+// CIE XYZ to linear sRGB with D65 white
+pub fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.969266 * xyz.x() + 1.8760108 * xyz.y() + 0.041556 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+// Linear RGB of a reflectance spectrum under equal energy light, sampled
+// at n wavelengths across the visible range. Each channel is normalized so
+// that a spectrum of all ones is white (1, 1, 1).
+pub fn spectrum_to_rgb<F: Fn(Float) -> Float>(f: F, n: usize) -> Vec3 {
+    let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+    let mut white = Vec3::new(0.0, 0.0, 0.0);
+    for i in 0..n {
+        let lambda = LAMBDA_MIN + (i as Float + 0.5) / n as Float * (LAMBDA_MAX - LAMBDA_MIN);
+        let cmf = cie_xyz(lambda);
+        xyz += cmf * f(lambda);
+        white += cmf;
+    }
+    xyz_to_rgb(xyz) / xyz_to_rgb(white)
+}
+
+// Linear RGB weight of a path carrying only wavelength lambda, drawn
+// uniformly from the visible range, so that weights average to white
+pub fn wavelength_to_rgb(lambda: Float) -> Vec3 {
+    static WHITE: OnceLock<Vec3> = OnceLock::new();
+    let white = WHITE.get_or_init(|| {
+        let n = 1000;
+        let mut white = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let lambda = LAMBDA_MIN + (i as Float + 0.5) / n as Float * (LAMBDA_MAX - LAMBDA_MIN);
+            white += xyz_to_rgb(cie_xyz(lambda)) / n as Float;
+        }
+        white
+    });
+    xyz_to_rgb(cie_xyz(lambda)) / *white
+}
+
+// Uniformly distributed wavelength in the visible range
+pub fn sample_wavelength(u: Float) -> Float {
+    LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+// Spectral radiance of a blackbody at temperature kelvin and wavelength
+// lambda in nm (Planck's law), in W / (sr m^2 m)
+pub fn planck(lambda: Float, kelvin: Float) -> Float {
+    let (c, h, kb) = (299_792_458.0f64, 6.626_070_15e-34f64, 1.380_649e-23f64);
+    let l = lambda as f64 * 1e-9;
+    let le = 2.0 * h * c * c / (l.powi(5) * ((h * c / (l * kb * kelvin as f64)).exp() - 1.0));
+    le as Float
+}
+
+// Linear RGB color of a blackbody at temperature kelvin, scaled to unit
+// luminance so that it only sets the hue of a light
+pub fn blackbody_rgb(kelvin: Float) -> Vec3 {
+    let peak = planck(2.897_772e6 / kelvin, kelvin);
+    let rgb = spectrum_to_rgb(|lambda| planck(lambda, kelvin) / peak, 64);
+    rgb / luminance(rgb)
+}
+
+// Relative luminance of linear sRGB
+pub fn luminance(rgb: Vec3) -> Float {
+    0.2126 * rgb.x() + 0.7152 * rgb.y() + 0.0722 * rgb.z()
+}
+
+// Value at lambda of a quantity given at the wavelengths RGB channels are
+// taken to represent, 650, 550 and 450nm, interpolated linearly between
+// them and held beyond
+pub fn rgb_at(v: Vec3, lambda: Float) -> Float {
+    if lambda >= 650.0 {
+        v.x()
+    } else if lambda >= 550.0 {
+        let t = (lambda - 550.0) / 100.0;
+        v.y() + (v.x() - v.y()) * t
+    } else if lambda >= 450.0 {
+        let t = (lambda - 450.0) / 100.0;
+        v.z() + (v.y() - v.z()) * t
+    } else {
+        v.z()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_and_primaries() {
+        let white = spectrum_to_rgb(|_| 1.0, 32);
+        assert!((white - Vec3::new(1.0, 1.0, 1.0)).mag() < 1e-4);
+
+        let red = spectrum_to_rgb(|l| if l > 600.0 { 1.0 } else { 0.0 }, 32);
+        let blue = spectrum_to_rgb(|l| if l < 490.0 { 1.0 } else { 0.0 }, 32);
+        assert!(red.x() > red.y() && red.x() > red.z());
+        assert!(blue.z() > blue.x() && blue.z() > blue.y());
+
+        let n = 10000;
+        let mut mean = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            mean +=
+                wavelength_to_rgb(sample_wavelength((i as Float + 0.5) / n as Float)) / n as Float;
+        }
+        assert!((mean - Vec3::new(1.0, 1.0, 1.0)).mag() < 1e-3, "{:?}", mean);
+    }
+
+    #[test]
+    fn blackbody() {
+        // Wien's displacement law
+        let t = 5000.0;
+        let peak = 2.897_772e6 / t;
+        assert!(planck(peak, t) > planck(peak - 20.0, t));
+        assert!(planck(peak, t) > planck(peak + 20.0, t));
+
+        // White is equal energy, which a blackbody near 5500K is close
+        // to. Incandescent light is orange, daylight bluer.
+        let noon = blackbody_rgb(5500.0);
+        assert!((luminance(noon) - 1.0).abs() < 1e-4);
+        assert!((noon - Vec3::new(1.0, 1.0, 1.0)).mag() < 0.1, "{:?}", noon);
+        let sky = blackbody_rgb(9000.0);
+        assert!(sky.z() > sky.y() && sky.y() > sky.x());
+        let tungsten = blackbody_rgb(2700.0);
+        assert!(tungsten.x() > tungsten.y() && tungsten.y() > tungsten.z());
+    }
+}
@@ -1,8 +1,40 @@
 use crate::perlin::Perlin;
-use crate::vec3::Vec3;
+use crate::vec3::{consts, Float, Vec3};
 
-pub trait Texture : Sync + Send {
-    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3;
+// Extent of a pixel's footprint on a surface, in world space and in uv
+#[derive(Copy, Clone, Default)]
+pub struct Footprint {
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub dudx: Float,
+    pub dvdx: Float,
+    pub dudy: Float,
+    pub dvdy: Float,
+}
+
+impl Footprint {
+    pub fn width(&self) -> Float {
+        self.dpdx.mag().max(self.dpdy.mag())
+    }
+    pub fn uv_width(&self) -> Float {
+        self.dudx
+            .abs()
+            .max(self.dvdx.abs())
+            .max(self.dudy.abs())
+            .max(self.dvdy.abs())
+    }
+}
+
+pub trait Texture: Sync + Send {
+    fn value(&self, u: Float, v: Float, p: &Vec3) -> Vec3;
+    // Average of the texture over a footprint; point samples by default
+    fn filtered(&self, u: Float, v: Float, p: &Vec3, _fp: &Footprint) -> Vec3 {
+        self.value(u, v, p)
+    }
+    // Coverage in [0, 1] for textures that carry one; opaque by default
+    fn alpha(&self, _u: Float, _v: Float, _p: &Vec3) -> Float {
+        1.0
+    }
 }
 
 pub struct ConstantTexture {
@@ -16,7 +48,7 @@ impl ConstantTexture {
 }
 
 impl Texture for ConstantTexture {
-    fn value(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
+    fn value(&self, _u: Float, _v: Float, _p: &Vec3) -> Vec3 {
         self.color
     }
 }
@@ -33,7 +65,7 @@ impl CheckerTexture {
 }
 
 impl Texture for CheckerTexture {
-    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+    fn value(&self, u: Float, v: Float, p: &Vec3) -> Vec3 {
         let sines = (10.0 * p.x()).sin() * (10.0 * p.y()).sin() * (10.0 * p.z()).sin();
         if sines < 0.0 {
             self.odd.value(u, v, p)
@@ -41,15 +73,32 @@ impl Texture for CheckerTexture {
             self.even.value(u, v, p)
         }
     }
+    // Fades to the average of both textures as the footprint covers more
+    // of a checker cell
+    fn filtered(&self, u: Float, v: Float, p: &Vec3, fp: &Footprint) -> Vec3 {
+        let cell = consts::PI / 10.0;
+        let blend = (fp.width() / cell).min(1.0);
+        let sines = (10.0 * p.x()).sin() * (10.0 * p.y()).sin() * (10.0 * p.z()).sin();
+        let point = if sines < 0.0 {
+            self.odd.filtered(u, v, p, fp)
+        } else {
+            self.even.filtered(u, v, p, fp)
+        };
+        if blend <= 0.0 {
+            return point;
+        }
+        let average = 0.5 * (self.odd.filtered(u, v, p, fp) + self.even.filtered(u, v, p, fp));
+        (1.0 - blend) * point + blend * average
+    }
 }
 
 pub struct NoiseTexture {
-    scale: f32,
+    scale: Float,
     noise: Perlin,
 }
 
 impl NoiseTexture {
-    pub fn new(scale: f32, noise: Perlin) -> Self {
+    pub fn new(scale: Float, noise: Perlin) -> Self {
         Self {
             scale: scale,
             noise: noise,
@@ -58,7 +107,7 @@ impl NoiseTexture {
 }
 
 impl Texture for NoiseTexture {
-    fn value(&self, _u: f32, _v: f32, p: &Vec3) -> Vec3 {
+    fn value(&self, _u: Float, _v: Float, p: &Vec3) -> Vec3 {
         // Vec3::new(1.0, 1.0, 1.0)
         //     * 0.50
         //     * (1.0 + (self.scale * p.x() + 10.0 * self.noise.turb(p, 7)).sin())
@@ -69,29 +118,182 @@ impl Texture for NoiseTexture {
     }
 }
 
-pub struct ImageTexture {
-    data: Vec<u8>,
+struct MipLevel {
+    texels: Vec<Vec3>,
     nx: i32,
     ny: i32,
 }
 
+impl MipLevel {
+    fn texel(&self, i: i32, j: i32) -> Vec3 {
+        let i = i.max(0).min(self.nx - 1);
+        let j = j.max(0).min(self.ny - 1);
+        self.texels[(i + self.nx * j) as usize]
+    }
+    fn bilinear(&self, u: Float, v: Float) -> Vec3 {
+        let x = u * self.nx as Float - 0.5;
+        let y = (1.0 - v) * self.ny as Float - 0.5;
+        let (i, j) = (x.floor(), y.floor());
+        let (dx, dy) = (x - i, y - j);
+        let (i, j) = (i as i32, j as i32);
+        (1.0 - dx) * (1.0 - dy) * self.texel(i, j)
+            + dx * (1.0 - dy) * self.texel(i + 1, j)
+            + (1.0 - dx) * dy * self.texel(i, j + 1)
+            + dx * dy * self.texel(i + 1, j + 1)
+    }
+    // Box filtered half resolution copy
+    fn downsample(&self) -> MipLevel {
+        let nx = (self.nx / 2).max(1);
+        let ny = (self.ny / 2).max(1);
+        let mut texels = Vec::with_capacity((nx * ny) as usize);
+        for j in 0..ny {
+            for i in 0..nx {
+                let sum = self.texel(2 * i, 2 * j)
+                    + self.texel(2 * i + 1, 2 * j)
+                    + self.texel(2 * i, 2 * j + 1)
+                    + self.texel(2 * i + 1, 2 * j + 1);
+                texels.push(sum * 0.25);
+            }
+        }
+        MipLevel {
+            texels: texels,
+            nx: nx,
+            ny: ny,
+        }
+    }
+}
+
+pub struct ImageTexture {
+    levels: Vec<MipLevel>,
+    // Full resolution alpha channel, stored in every component
+    alpha: Option<MipLevel>,
+}
+
 impl ImageTexture {
     pub fn new(pixels: Vec<u8>, a: i32, b: i32) -> Self {
-        Self{ data: pixels, nx: a, ny: b }
+        let texels = pixels
+            .chunks(3)
+            .map(|c| Vec3::new(c[0] as Float, c[1] as Float, c[2] as Float) / 255.0)
+            .collect();
+        Self::from_texels(texels, None, a, b)
+    }
+    // From RGBA pixels, keeping the alpha channel for cutouts
+    pub fn new_rgba(pixels: Vec<u8>, a: i32, b: i32) -> Self {
+        let texels = pixels
+            .chunks(4)
+            .map(|c| Vec3::new(c[0] as Float, c[1] as Float, c[2] as Float) / 255.0)
+            .collect();
+        let alpha = pixels
+            .chunks(4)
+            .map(|c| Vec3::new(1.0, 1.0, 1.0) * (c[3] as Float / 255.0))
+            .collect();
+        Self::from_texels(
+            texels,
+            Some(MipLevel {
+                texels: alpha,
+                nx: a,
+                ny: b,
+            }),
+            a,
+            b,
+        )
+    }
+    fn from_texels(texels: Vec<Vec3>, alpha: Option<MipLevel>, a: i32, b: i32) -> Self {
+        let mut levels = vec![MipLevel {
+            texels: texels,
+            nx: a,
+            ny: b,
+        }];
+        while levels.last().is_some_and(|l| l.nx > 1 || l.ny > 1) {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
+        Self { levels, alpha }
     }
 }
 
 impl Texture for ImageTexture {
-    fn value(&self, u: f32, v: f32, _p: &Vec3) -> Vec3 {
-        let mut i = (u * self.nx as f32) as i32;
-        let mut j = ((1.0-v) * self.ny as f32 - 0.001) as i32;
-        if i < 0 { i = 0; }
-        if j < 0 { j = 0; }
-        if i > (self.nx - 1) {  i = self.nx - 1; }
-        if j > (self.ny - 1) {  j = self.ny - 1; }
-        let r = self.data[3*i as usize + (3*self.nx*j) as usize] as f32 / 255.0;
-        let g = self.data[3*i as usize + (3*self.nx*j + 1) as usize] as f32 / 255.0;
-        let b = self.data[3*i as usize + (3*self.nx*j + 2) as usize] as f32 / 255.0;
-        Vec3::new(r, g, b)
+    fn value(&self, u: Float, v: Float, _p: &Vec3) -> Vec3 {
+        let base = &self.levels[0];
+        let i = (u * base.nx as Float) as i32;
+        let j = ((1.0 - v) * base.ny as Float - 0.001) as i32;
+        base.texel(i, j)
+    }
+    // Trilinear lookup in the mip level whose texels match the footprint
+    fn filtered(&self, u: Float, v: Float, p: &Vec3, fp: &Footprint) -> Vec3 {
+        let base = &self.levels[0];
+        let width = fp.uv_width() * base.nx.max(base.ny) as Float;
+        if width <= 1.0 {
+            return if width > 0.0 {
+                base.bilinear(u, v)
+            } else {
+                self.value(u, v, p)
+            };
+        }
+        let last = (self.levels.len() - 1) as Float;
+        let level = width.log2().min(last);
+        let lo = level.floor();
+        let d = level - lo;
+        let lo = lo as usize;
+        let hi = (lo + 1).min(self.levels.len() - 1);
+        (1.0 - d) * self.levels[lo].bilinear(u, v) + d * self.levels[hi].bilinear(u, v)
+    }
+    fn alpha(&self, u: Float, v: Float, _p: &Vec3) -> Float {
+        self.alpha
+            .as_ref()
+            .map_or(1.0, |alpha| alpha.bilinear(u, v).x())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 8x8 black and white texel checkerboard
+    fn checker_image() -> ImageTexture {
+        let mut pixels = Vec::new();
+        for j in 0..8 {
+            for i in 0..8 {
+                let c = if (i + j) % 2 == 0 { 255 } else { 0 };
+                pixels.extend_from_slice(&[c, c, c]);
+            }
+        }
+        ImageTexture::new(pixels, 8, 8)
+    }
+
+    #[test]
+    fn wide_footprint_averages_image() {
+        let tex = checker_image();
+        let p = Vec3::default();
+        let fp = Footprint {
+            dudx: 1.0,
+            dvdy: 1.0,
+            ..Footprint::default()
+        };
+        let c = tex.filtered(0.3, 0.6, &p, &fp);
+        assert!((c.x() - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn zero_footprint_point_samples_image() {
+        let tex = checker_image();
+        let p = Vec3::default();
+        let c = tex.filtered(0.5 / 8.0, 1.0 - 0.5 / 8.0, &p, &Footprint::default());
+        assert_eq!(c.x(), tex.value(0.5 / 8.0, 1.0 - 0.5 / 8.0, &p).x());
+    }
+
+    #[test]
+    fn rgba_image_keeps_alpha() {
+        // Opaque left column, transparent right column
+        let pixels = vec![
+            10, 20, 30, 255, 10, 20, 30, 0, //
+            10, 20, 30, 255, 10, 20, 30, 0,
+        ];
+        let tex = ImageTexture::new_rgba(pixels, 2, 2);
+        let p = Vec3::default();
+        assert!((tex.value(0.25, 0.5, &p).y() - 20.0 / 255.0).abs() < 1e-6);
+        assert_eq!(tex.alpha(0.0, 0.5, &p), 1.0);
+        assert_eq!(tex.alpha(1.0, 0.5, &p), 0.0);
+        assert_eq!(checker_image().alpha(0.5, 0.5, &p), 1.0);
     }
 }
@@ -0,0 +1,305 @@
+use rayon::prelude::*;
+
+use crate::camera::Camera;
+use crate::hit::*;
+use crate::vec3::*;
+
+// What the camera ray through a pixel center found, kept for every pixel so
+// that outlines can compare neighbours
+#[derive(Clone, Copy)]
+struct GSample {
+    object_id: Option<usize>,
+    t: Float,
+    // Geometric normal, facing the camera
+    normal: Vec3,
+    color: Vec3,
+}
+
+// Non-photorealistic integrator for line-art diagrams. Surfaces get a few
+// flat bands of diffuse light from a single point light and a hard rim
+// highlight, and outlines are drawn wherever neighbouring camera rays
+// disagree on object, depth or normal. Only primary rays are traced, so
+// there are no shadows, reflections or noise.
+pub struct ToonShader {
+    light: Vec3,
+    bands: u32,
+    ambient: Float,
+    // Rim light is (1 - |n.v|)^rim_power, shown at rim_strength where it
+    // exceeds rim_threshold and not at all elsewhere
+    rim_power: Float,
+    rim_threshold: Float,
+    rim_strength: Float,
+    // Relative change in distance between neighbours that counts as an edge
+    depth_threshold: Float,
+    // Neighbouring normals meeting at a larger angle form a crease
+    crease_cos: Float,
+    line_color: Vec3,
+    background: Vec3,
+}
+
+impl ToonShader {
+    pub fn new(light: Vec3) -> Self {
+        Self {
+            light,
+            bands: 3,
+            ambient: 0.3,
+            rim_power: 2.0,
+            rim_threshold: 0.5,
+            rim_strength: 0.25,
+            depth_threshold: 0.1,
+            crease_cos: (30.0 as Float).to_radians().cos(),
+            line_color: Vec3::new(0.0, 0.0, 0.0),
+            background: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn with_bands(mut self, bands: u32) -> Self {
+        self.bands = bands.max(1);
+        self
+    }
+
+    // Renders an nx by ny image, with rows from the top as they are written
+    // out
+    pub fn render(&self, cam: &Camera, world: &dyn Hittable, nx: u32, ny: u32) -> Vec<Vec3> {
+        let (w, h) = (nx as usize, ny as usize);
+        let gbuffer: Vec<Option<GSample>> = (0..w * h)
+            .into_par_iter()
+            .map(|idx| {
+                let (x, y) = (idx % w, idx / w);
+                let s = (x as Float + 0.5) / nx as Float;
+                let t = (ny as Float - y as Float - 0.5) / ny as Float;
+                self.trace(cam.get_pinhole_ray(s, t), world)
+            })
+            .collect();
+
+        (0..w * h)
+            .into_par_iter()
+            .map(|idx| {
+                let (x, y) = (idx % w, idx / w);
+                let here = &gbuffer[idx];
+                let right = if x + 1 < w {
+                    gbuffer.get(idx + 1)
+                } else {
+                    None
+                };
+                let below = if y + 1 < h {
+                    gbuffer.get(idx + w)
+                } else {
+                    None
+                };
+                let outline = right
+                    .into_iter()
+                    .chain(below)
+                    .any(|other| self.is_edge(here, other));
+                if outline {
+                    self.line_color
+                } else {
+                    here.map_or(self.background, |g| g.color)
+                }
+            })
+            .collect()
+    }
+
+    fn trace(&self, r: Ray, world: &dyn Hittable) -> Option<GSample> {
+        let mut hit = world.hit(r, 0.0, Float::MAX)?;
+        hit.compute_differentials(&r);
+        let material = hit.material.clone();
+        material.apply_shading(&mut hit);
+
+        let v = -r.direction().unit();
+        let facing = |n: Vec3| if dot(n, v) < 0.0 { -n } else { n };
+        let normal = facing(hit.normal.unit());
+
+        let emitted = hit.material.emitted(&r, &hit, hit.u, hit.v, &hit.p);
+        let color = if emitted.max_component() > 0.0 {
+            emitted / emitted.max_component()
+        } else {
+            let n = facing(hit.shading_normal().unit());
+            let l = (self.light - hit.p).unit();
+            let band = self.quantize(dot(n, l).max(0.0));
+            let rim = (1.0 - dot(n, v).abs()).powf(self.rim_power);
+            let rim = if rim > self.rim_threshold {
+                self.rim_strength
+            } else {
+                0.0
+            };
+            let lit = self.ambient + (1.0 - self.ambient) * band + rim;
+            (self.base_color(&hit) * lit).min(Vec3::new(1.0, 1.0, 1.0))
+        };
+
+        Some(GSample {
+            object_id: hit.object_id,
+            t: hit.t,
+            normal,
+            color,
+        })
+    }
+
+    // Diffuse albedo where the material has one, found by evaluating it
+    // straight back along the normal. Purely specular surfaces have none and
+    // are drawn light gray.
+    fn base_color(&self, hit: &HitRecord) -> Vec3 {
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        if !hit.material.flags(hit).has_non_specular() {
+            return Vec3::new(0.9, 0.9, 0.9);
+        }
+        hit.material.eval(hit, &up, &up) * consts::PI
+    }
+
+    // Maps a cosine in [0, 1] onto evenly spaced flat bands, the darkest at 0
+    fn quantize(&self, cos: Float) -> Float {
+        if self.bands <= 1 {
+            return 1.0;
+        }
+        let n = self.bands as Float;
+        (cos * n).floor().min(n - 1.0) / (n - 1.0)
+    }
+
+    fn is_edge(&self, a: &Option<GSample>, b: &Option<GSample>) -> bool {
+        match (a, b) {
+            (None, None) => false,
+            (Some(a), Some(b)) => {
+                a.object_id != b.object_id
+                    || (a.t - b.t).abs() > self.depth_threshold * a.t.min(b.t)
+                    || dot(a.normal, b.normal) < self.crease_cos
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::obj::{BoxShape, Sphere, Tagged, XYRect};
+    use crate::texture::ConstantTexture;
+    use std::sync::Arc;
+
+    fn gray() -> Arc<Lambertian> {
+        Arc::new(Lambertian::new(Arc::new(ConstantTexture::new(Vec3::new(
+            0.5, 0.5, 0.5,
+        )))))
+    }
+
+    // Renders one tagged object n by n, looking at the origin from a point,
+    // with black lines
+    fn render(object: Arc<dyn Hittable>, from: Vec3, n: u32) -> Vec<Vec3> {
+        let world: Vec<Arc<dyn Hittable>> = vec![Arc::new(Tagged::new(object, 1, None))];
+        let cam = Camera::new(
+            from,
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            0.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        let shader = ToonShader::new(Vec3::new(0.0, 10.0, 10.0)).with_bands(4);
+        shader.render(&cam, &world, n, n)
+    }
+
+    fn is_line(c: &Vec3) -> bool {
+        c.max_component() == 0.0
+    }
+
+    // Columns of the middle row that are outlined, and the first and last
+    // columns that are not background
+    fn middle_row(image: &[Vec3], n: u32) -> (Vec<u32>, u32, u32) {
+        let row = &image[(n * (n / 2)) as usize..(n * (n / 2 + 1)) as usize];
+        let lines = (0..n).filter(|&x| is_line(&row[x as usize])).collect();
+        let mut covered = (0..n).filter(|&x| row[x as usize].min_component() < 1.0);
+        let first = covered.next().unwrap();
+        (lines, first, covered.next_back().unwrap_or(first))
+    }
+
+    // Column, in pixels from the left, that a point on the horizon of a
+    // camera at from looking at the origin projects to
+    fn column(from: Vec3, p: Vec3, n: u32) -> Float {
+        let w = from.unit();
+        let u = cross(Vec3::new(0.0, 1.0, 0.0), w).unit();
+        let (x, z) = (dot(p - from, u), -dot(p - from, w));
+        let half_width = (20.0 as Float).to_radians().tan();
+        (0.5 + 0.5 * x / (z * half_width)) * n as Float
+    }
+
+    #[test]
+    fn outlines_and_bands() {
+        let sphere = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, gray()));
+        let n = 41;
+        let image = render(sphere, Vec3::new(0.0, 0.0, 5.0), n);
+        let at = |x: u32, y: u32| image[(y * n + x) as usize];
+
+        // Background in the corner, and on the middle row an outline at
+        // either end of the sphere and none across its smooth interior
+        assert!(at(0, 0).min_component() == 1.0);
+        let (lines, first, last) = middle_row(&image, n);
+        assert!(last - first > 10, "{} {}", first, last);
+        assert_eq!(lines, vec![first, last]);
+
+        // Facing the camera the light is at cos 0.67, in the third of four
+        // bands, which lifts gray 0.5 from the ambient 0.3 by two thirds of
+        // the rest
+        let lit = |band: Float| 0.5 * (0.3 + 0.7 * band / 3.0);
+        let center = at(n / 2, n / 2);
+        assert!((center.x() - lit(2.0)).abs() < 1e-5, "{:?}", center);
+
+        // Every band shows, with and without the rim light's quarter near the
+        // outline
+        let mut expected: Vec<Float> = (0..4)
+            .flat_map(|band| vec![lit(band as Float), lit(band as Float) + 0.5 * 0.25])
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut levels: Vec<Float> = image
+            .iter()
+            .filter(|c| c.y() == c.x() && c.x() > 0.0 && c.x() < 1.0)
+            .map(|c| c.x())
+            .collect();
+        levels.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        levels.dedup_by(|a, b| (*a - *b).abs() < 1e-5);
+        assert_eq!(levels.len(), expected.len(), "{:?}", levels);
+        for (level, e) in levels.iter().zip(&expected) {
+            assert!((level - e).abs() < 1e-5, "{:?}", levels);
+        }
+    }
+
+    #[test]
+    fn creases_and_depth_steps() {
+        let n = 41;
+        // An outline at either end and one line inside, drawn on the pixel
+        // left of the edge at p, so that the edge lies within a pixel of the
+        // line's right side
+        let check = |image: &[Vec3], from: Vec3, p: Vec3| {
+            let (lines, first, last) = middle_row(image, n);
+            assert_eq!(lines.len(), 3, "{:?}", lines);
+            assert_eq!((lines[0], lines[2]), (first, last));
+            let expected = column(from, p, n);
+            let inner = lines[1] as Float + 1.0;
+            assert!((inner - expected).abs() <= 1.0, "{:?} {}", lines, expected);
+        };
+
+        // A cube seen corner on has a crease between its faces
+        let cube = Arc::new(BoxShape::new(
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            gray(),
+        ));
+        let from = Vec3::new(4.0, 0.0, 3.6);
+        check(&render(cube, from, n), from, Vec3::new(1.0, 0.0, 1.0));
+
+        // One card in front of another of the same object, parallel to it,
+        // steps in depth where it ends
+        let cards: Vec<Arc<dyn Hittable>> = vec![
+            Arc::new(XYRect::new(-1.0, 0.1, -1.0, 1.0, 0.5, gray())),
+            Arc::new(XYRect::new(-1.0, 1.0, -1.0, 1.0, -0.5, gray())),
+        ];
+        let from = Vec3::new(0.0, 0.0, 5.0);
+        check(
+            &render(Arc::new(cards), from, n),
+            from,
+            Vec3::new(0.1, 0.0, 0.5),
+        );
+    }
+}
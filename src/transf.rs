@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use crate::hit::Hittable;
-use crate::vec3::Vec3;
+use crate::vec3::{Float, Vec3};
 
 pub struct FlipNormals {
     pub obj_ref: Arc<dyn Hittable>,
@@ -29,12 +29,12 @@ impl Translate {
 
 pub struct RotateY {
     pub obj_ref: Arc<dyn Hittable>,
-    pub sin_theta: f32,
-    pub cos_theta: f32,
+    pub sin_theta: Float,
+    pub cos_theta: Float,
 }
 
 impl RotateY {
-    pub fn new(obj_ref: Arc<dyn Hittable>, angle: f32) -> Self {
+    pub fn new(obj_ref: Arc<dyn Hittable>, angle: Float) -> Self {
         let radians = angle.to_radians();
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
@@ -44,4 +44,12 @@ impl RotateY {
             cos_theta: cos_theta,
         }
     }
+    // Rotates a vector from the object's frame back into the world
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x() + self.sin_theta * v.z(),
+            v.y(),
+            -self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
 }
@@ -1,14 +1,14 @@
-use crate::vec3::Vec3;
+use crate::vec3::{consts, dot, Float, Vec3};
 use rand::Rng;
 
 #[inline]
-pub fn rand_float() -> f32 {
+pub fn rand_float() -> Float {
     let mut rng = rand::thread_rng();
     rng.gen()
 }
 
 #[inline]
-pub fn rand_float_range(min: f32, max: f32) -> f32 {
+pub fn rand_float_range(min: Float, max: Float) -> Float {
     let mut rng = rand::thread_rng();
     rng.gen_range(min, max)
 }
@@ -36,19 +36,18 @@ pub fn random_in_unit_disk() -> Vec3 {
 
 #[inline]
 pub fn random_unit_vector() -> Vec3 {
-    let a = rand_float() * 2.0 * std::f32::consts::PI;
+    let a = rand_float() * 2.0 * consts::PI;
     let z = rand_float() * 2.0 - 1.0;
     let r = (1.0 - z * z).sqrt();
     return Vec3::new(r * a.cos(), r * a.sin(), z);
 }
 
 #[inline]
-pub fn random_cosine_direction() -> Vec3 {
-    let r1 = rand_float();
-    let r2 = rand_float();
+pub fn cosine_sample_hemisphere(u: (Float, Float)) -> Vec3 {
+    let (r1, r2) = u;
     let z = (1.0 - r2).sqrt();
 
-    let phi = 2.0 * std::f32::consts::PI * r1;
+    let phi = 2.0 * consts::PI * r1;
     let x = phi.cos() * r2.sqrt();
     let y = phi.sin() * r2.sqrt();
 
@@ -56,12 +55,20 @@ pub fn random_cosine_direction() -> Vec3 {
 }
 
 #[inline]
-pub fn random_to_sphere(radius: f32, dist_sqrd: f32) -> Vec3 {
+pub fn uniform_sample_sphere(u: (Float, Float)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * consts::PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[inline]
+pub fn random_to_sphere(radius: Float, dist_sqrd: Float) -> Vec3 {
     let r1 = rand_float();
     let r2 = rand_float();
     let z = 1.0 + r2 * ((1.0 - radius * radius / dist_sqrd).sqrt() - 1.0);
 
-    let phi = 2.0 * std::f32::consts::PI * r1;
+    let phi = 2.0 * consts::PI * r1;
     let x = phi.cos() * (1.0 - z * z).sqrt();
     let y = phi.sin() * (1.0 - z * z).sqrt();
 
@@ -69,13 +76,88 @@ pub fn random_to_sphere(radius: f32, dist_sqrd: f32) -> Vec3 {
 }
 
 #[inline]
-pub fn schlick(cosine: f32, ref_idx: f32) -> f32 {
+pub fn schlick(cosine: Float, ref_idx: Float) -> Float {
     let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     r0 = r0 * r0;
 
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
+// Conservative bound on the relative error of n chained floating point
+// operations, as in pbrt
+#[inline]
+pub fn gamma(n: i32) -> Float {
+    let eps = Float::EPSILON * 0.5;
+    (n as Float * eps) / (1.0 - n as Float * eps)
+}
+
+#[inline]
+pub fn next_float_up(v: Float) -> Float {
+    if v.is_infinite() && v > 0.0 {
+        return v;
+    }
+    let v = if v == -0.0 { 0.0 } else { v };
+    let bits = v.to_bits();
+    if v >= 0.0 {
+        Float::from_bits(bits + 1)
+    } else {
+        Float::from_bits(bits - 1)
+    }
+}
+
+#[inline]
+pub fn next_float_down(v: Float) -> Float {
+    if v.is_infinite() && v < 0.0 {
+        return v;
+    }
+    let v = if v == 0.0 { -0.0 } else { v };
+    let bits = v.to_bits();
+    if v > 0.0 {
+        Float::from_bits(bits - 1)
+    } else {
+        Float::from_bits(bits + 1)
+    }
+}
+
+// Pushes a hit point off the surface along the normal by at least its
+// floating point error, so rays spawned from it cannot re-hit the surface
+#[inline]
+pub fn offset_ray_origin(p: Vec3, p_error: Vec3, n: Vec3, w: Vec3) -> Vec3 {
+    let d = dot(n.abs(), p_error);
+    let mut offset = d * n;
+    if dot(w, n) < 0.0 {
+        offset *= -1.0;
+    }
+    let mut po = p + offset;
+    for i in 0..3 {
+        if offset[i] > 0.0 {
+            po[i] = next_float_up(po[i]);
+        } else if offset[i] < 0.0 {
+            po[i] = next_float_down(po[i]);
+        }
+    }
+    po
+}
+
+// Deterministic number in [0, 1) hashed from two vectors, e.g. a ray's
+// origin and direction, so that repeated tests of one ray agree
+pub fn hash_float(a: Vec3, b: Vec3) -> Float {
+    // FNV-1a over the bits, then the MurmurHash3 finalizer
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for i in 0..3 {
+        for &x in [a[i], b[i]].iter() {
+            h ^= (x as f64).to_bits();
+            h = h.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    (h >> 40) as Float / (1u64 << 24) as Float
+}
+
 #[inline]
 pub fn de_nan(c: &Vec3) -> Vec3 {
     let mut temp: Vec3 = *c;
@@ -1,19 +1,60 @@
 use std::ops::*;
 
-#[derive(Copy, Clone, Default, Debug)]
+// Scalar type used throughout the renderer; build with the `f64` feature
+// for large scenes that need the extra precision
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
+
+// Padded to four lanes and aligned to a full SIMD register, so the lane-wise
+// operators below compile to packed instructions. The fourth lane carries no
+// meaning and is never read by reductions such as dot.
+#[derive(Copy, Clone, Default)]
+#[cfg_attr(not(feature = "f64"), repr(C, align(16)))]
+#[cfg_attr(feature = "f64", repr(C, align(32)))]
 pub struct Vec3 {
-    pub e0: f32,
-    pub e1: f32,
-    pub e2: f32,
+    e: [Float; 4],
+}
+
+macro_rules! lanewise {
+    ($a:expr, $b:expr, $op:tt) => {
+        Vec3 {
+            e: [
+                $a.e[0] $op $b.e[0],
+                $a.e[1] $op $b.e[1],
+                $a.e[2] $op $b.e[2],
+                $a.e[3] $op $b.e[3],
+            ],
+        }
+    };
+}
+
+macro_rules! lanewise_assign {
+    ($a:expr, $b:expr, $op:tt) => {
+        $a.e[0] $op $b.e[0];
+        $a.e[1] $op $b.e[1];
+        $a.e[2] $op $b.e[2];
+        $a.e[3] $op $b.e[3];
+    };
+}
+
+impl std::fmt::Debug for Vec3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Vec3({}, {}, {})", self.e[0], self.e[1], self.e[2])
+    }
 }
 
 impl Index<u32> for Vec3 {
-    type Output = f32;
+    type Output = Float;
     fn index(&self, i: u32) -> &Self::Output {
         match i {
-            0 => &self.e0,
-            1 => &self.e1,
-            2 => &self.e2,
+            0..=2 => &self.e[i as usize],
             _ => panic!(),
         }
     }
@@ -22,9 +63,7 @@ impl Index<u32> for Vec3 {
 impl IndexMut<u32> for Vec3 {
     fn index_mut(&mut self, index: u32) -> &mut Self::Output {
         match index {
-            0 => &mut self.e0,
-            1 => &mut self.e1,
-            2 => &mut self.e2,
+            0..=2 => &mut self.e[index as usize],
             _ => panic!(),
         }
     }
@@ -33,29 +72,34 @@ impl IndexMut<u32> for Vec3 {
 impl Add for Vec3 {
     type Output = Self;
     fn add(self, other: Self) -> Self {
-        Self {
-            e0: self.e0 + other.e0,
-            e1: self.e1 + other.e1,
-            e2: self.e2 + other.e2,
-        }
+        lanewise!(self, other, +)
     }
 }
 
 impl AddAssign for Vec3 {
     fn add_assign(&mut self, other: Self) {
-        self.e0 += other.e0;
-        self.e1 += other.e1;
-        self.e2 += other.e2;
+        lanewise_assign!(self, other, +=);
     }
 }
 
 impl Sub for Vec3 {
     type Output = Self;
     fn sub(self, other: Self) -> Self {
+        lanewise!(self, other, -)
+    }
+}
+
+impl SubAssign for Vec3 {
+    fn sub_assign(&mut self, other: Self) {
+        lanewise_assign!(self, other, -=);
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
         Self {
-            e0: self.e0 - other.e0,
-            e1: self.e1 - other.e1,
-            e2: self.e2 - other.e2,
+            e: [-self.e[0], -self.e[1], -self.e[2], -self.e[3]],
         }
     }
 }
@@ -63,60 +107,47 @@ impl Sub for Vec3 {
 impl Mul<Vec3> for Vec3 {
     type Output = Self;
     fn mul(self, rhs: Self) -> Self::Output {
-        Self {
-            e0: self.e0 * rhs.e0,
-            e1: self.e1 * rhs.e1,
-            e2: self.e2 * rhs.e2,
-        }
+        lanewise!(self, rhs, *)
     }
 }
 
-impl Mul<f32> for Vec3 {
+impl Mul<Float> for Vec3 {
     type Output = Self;
-    fn mul(self, rhs: f32) -> Self::Output {
-        Self {
-            e0: self.e0 * rhs,
-            e1: self.e1 * rhs,
-            e2: self.e2 * rhs,
-        }
+    fn mul(self, rhs: Float) -> Self::Output {
+        lanewise!(self, Vec3::splat(rhs), *)
     }
 }
 
-impl Mul<Vec3> for f32 {
+impl Mul<Vec3> for Float {
     type Output = Vec3;
     fn mul(self, rhs: Vec3) -> Vec3 {
-        Vec3 {
-            e0: rhs.e0 * self,
-            e1: rhs.e1 * self,
-            e2: rhs.e2 * self,
-        }
+        lanewise!(rhs, Vec3::splat(self), *)
     }
 }
 
-impl MulAssign<f32> for Vec3 {
-    fn mul_assign(&mut self, rhs: f32) {
-        self.e0 *= rhs;
-        self.e1 *= rhs;
-        self.e2 *= rhs;
+impl MulAssign<Float> for Vec3 {
+    fn mul_assign(&mut self, rhs: Float) {
+        lanewise_assign!(self, Vec3::splat(rhs), *=);
     }
 }
 
-impl Div<f32> for Vec3 {
+impl Div<Vec3> for Vec3 {
     type Output = Self;
-    fn div(self, rhs: f32) -> Self::Output {
-        Vec3 {
-            e0: self.e0 / rhs,
-            e1: self.e1 / rhs,
-            e2: self.e2 / rhs,
-        }
+    fn div(self, rhs: Self) -> Self::Output {
+        lanewise!(self, rhs, /)
     }
 }
 
-impl DivAssign<f32> for Vec3 {
-    fn div_assign(&mut self, rhs: f32) {
-        self.e0 /= rhs;
-        self.e1 /= rhs;
-        self.e2 /= rhs;
+impl Div<Float> for Vec3 {
+    type Output = Self;
+    fn div(self, rhs: Float) -> Self::Output {
+        lanewise!(self, Vec3::splat(rhs), /)
+    }
+}
+
+impl DivAssign<Float> for Vec3 {
+    fn div_assign(&mut self, rhs: Float) {
+        lanewise_assign!(self, Vec3::splat(rhs), /=);
     }
 }
 
@@ -134,35 +165,87 @@ impl std::iter::Sum<Vec3> for Vec3 {
 }
 
 impl Vec3 {
-    pub fn mag(&self) -> f32 {
+    pub fn mag(&self) -> Float {
         dot(*self, *self).sqrt()
     }
-    pub fn mag_sqrd(&self) -> f32 {
+    pub fn mag_sqrd(&self) -> Float {
         dot(*self, *self)
     }
     pub fn unit(&self) -> Vec3 {
         *self / self.mag()
     }
-    pub fn new(e0: f32, e1: f32, e2: f32) -> Vec3 {
+    pub fn abs(&self) -> Vec3 {
+        Vec3 {
+            e: [
+                self.e[0].abs(),
+                self.e[1].abs(),
+                self.e[2].abs(),
+                self.e[3].abs(),
+            ],
+        }
+    }
+    // Component-wise minimum
+    pub fn min(&self, o: Vec3) -> Vec3 {
         Vec3 {
-            e0: e0,
-            e1: e1,
-            e2: e2,
+            e: [
+                self.e[0].min(o.e[0]),
+                self.e[1].min(o.e[1]),
+                self.e[2].min(o.e[2]),
+                self.e[3].min(o.e[3]),
+            ],
         }
     }
-    pub fn x(&self) -> f32 {
-        self.e0
+    // Component-wise maximum
+    pub fn max(&self, o: Vec3) -> Vec3 {
+        Vec3 {
+            e: [
+                self.e[0].max(o.e[0]),
+                self.e[1].max(o.e[1]),
+                self.e[2].max(o.e[2]),
+                self.e[3].max(o.e[3]),
+            ],
+        }
     }
-    pub fn y(&self) -> f32 {
-        self.e1
+    // Component-wise self, with NaN components taken from o
+    pub fn nan_or(&self, o: Vec3) -> Vec3 {
+        let pick = |a: Float, b: Float| if a.is_nan() { b } else { a };
+        Vec3 {
+            e: [
+                pick(self.e[0], o.e[0]),
+                pick(self.e[1], o.e[1]),
+                pick(self.e[2], o.e[2]),
+                pick(self.e[3], o.e[3]),
+            ],
+        }
+    }
+    pub fn min_component(&self) -> Float {
+        self.e[0].min(self.e[1]).min(self.e[2])
     }
-    pub fn z(&self) -> f32 {
-        self.e2
+    pub fn max_component(&self) -> Float {
+        self.e[0].max(self.e[1]).max(self.e[2])
+    }
+    pub fn new(e0: Float, e1: Float, e2: Float) -> Vec3 {
+        Vec3 {
+            e: [e0, e1, e2, 0.0],
+        }
+    }
+    pub fn splat(v: Float) -> Vec3 {
+        Vec3 { e: [v, v, v, v] }
+    }
+    pub fn x(&self) -> Float {
+        self.e[0]
+    }
+    pub fn y(&self) -> Float {
+        self.e[1]
+    }
+    pub fn z(&self) -> Float {
+        self.e[2]
     }
 }
 
-pub fn dot(u: Vec3, v: Vec3) -> f32 {
-    u.e0 * v.e0 + u.e1 * v.e1 + u.e2 * v.e2
+pub fn dot(u: Vec3, v: Vec3) -> Float {
+    let p = u * v;
+    p.e[0] + p.e[1] + p.e[2]
 }
 
 // n is a unit vector that we are reflecting
@@ -171,14 +254,21 @@ pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
 }
 
 pub fn cross(v1: Vec3, v2: Vec3) -> Vec3 {
-    Vec3::new(
-        v1.e1 * v2.e2 - v1.e2 * v2.e1,
-        v1.e2 * v2.e0 - v1.e0 * v2.e2,
-        v1.e0 * v2.e1 - v1.e1 * v2.e0,
-    )
+    // Written as two lane shuffles so it vectorizes like the operators
+    let a = Vec3 {
+        e: [v1.e[1], v1.e[2], v1.e[0], 0.0],
+    } * Vec3 {
+        e: [v2.e[2], v2.e[0], v2.e[1], 0.0],
+    };
+    let b = Vec3 {
+        e: [v1.e[2], v1.e[0], v1.e[1], 0.0],
+    } * Vec3 {
+        e: [v2.e[1], v2.e[2], v2.e[0], 0.0],
+    };
+    a - b
 }
 
-pub fn refract(v: Vec3, n: Vec3, ni_over_nt: f32) -> Option<Vec3> {
+pub fn refract(v: Vec3, n: Vec3, ni_over_nt: Float) -> Option<Vec3> {
     let uv = v.unit();
     let dt = dot(uv, n);
     let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
@@ -191,11 +281,24 @@ pub fn refract(v: Vec3, n: Vec3, ni_over_nt: f32) -> Option<Vec3> {
     None
 }
 
+// Rays offset by one pixel in x and y on the image plane, carried along with
+// camera and specular rays to estimate texture footprints
+#[derive(Copy, Clone, Default)]
+pub struct RayDifferentials {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
+}
+
 #[derive(Copy, Clone, Default)]
 pub struct Ray {
     pub a: Vec3,
     pub b: Vec3,
-    pub time: f32,
+    pub time: Float,
+    pub differentials: Option<RayDifferentials>,
+    // In nm, for paths that have been narrowed to a single wavelength
+    pub wavelength: Option<Float>,
 }
 
 impl Ray {
@@ -205,19 +308,32 @@ impl Ray {
     pub fn origin(&self) -> Vec3 {
         self.a
     }
-    pub fn time(&self) -> f32 {
+    pub fn time(&self) -> Float {
         self.time
     }
-    pub fn point_at_parameter(&self, t: f32) -> Vec3 {
+    pub fn point_at_parameter(&self, t: Float) -> Vec3 {
         self.a + t * self.b
     }
-    pub fn new(a: Vec3, b: Vec3, t: f32) -> Ray {
+    pub fn new(a: Vec3, b: Vec3, t: Float) -> Ray {
         Ray {
             a: a,
             b: b,
             time: t,
+            differentials: None,
+            wavelength: None,
         }
     }
+    pub fn with_differentials(mut self, differentials: Option<RayDifferentials>) -> Ray {
+        self.differentials = differentials;
+        self
+    }
+    pub fn wavelength(&self) -> Option<Float> {
+        self.wavelength
+    }
+    pub fn with_wavelength(mut self, wavelength: Option<Float>) -> Ray {
+        self.wavelength = wavelength;
+        self
+    }
 }
 
 pub struct Onb {
@@ -245,7 +361,11 @@ impl Onb {
     pub fn local_vector(&self, a: &Vec3) -> Vec3 {
         a.x() * self.u() + a.y() * self.v() + a.z() * self.w()
     }
-    pub fn local_coordinates(&self, a: f32, b: f32, c: f32) -> Vec3 {
+    // Inverse of local_vector
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(dot(*a, self.u()), dot(*a, self.v()), dot(*a, self.w()))
+    }
+    pub fn local_coordinates(&self, a: Float, b: Float, c: Float) -> Vec3 {
         a * self.u() + b * self.v() + c * self.w()
     }
     pub fn build_from_w(&mut self, n: &Vec3) {